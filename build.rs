@@ -1,17 +1,72 @@
 use bootloader::DiskImageBuilder;
-use std::{env, path::PathBuf};
+use std::{env, fs, path::Path, path::PathBuf};
+use xmas_elf::{
+    ElfFile,
+    sections::SectionData,
+    symbol_table::{Entry, Type},
+};
 
 fn main() {
     let kernel_path = env::var("CARGO_BIN_FILE_KERNEL").unwrap();
-    let disk_builder = DiskImageBuilder::new(PathBuf::from(kernel_path));
+    let mut disk_builder = DiskImageBuilder::new(PathBuf::from(&kernel_path));
 
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let uefi_path = out_dir.join("popcorn-uefi.img");
     let bios_path = out_dir.join("popcorn-bios.img");
 
+    // the kernel's function symbols go in as the ramdisk, for backtraces to resolve addresses with
+    let symbols_path = out_dir.join("kernel-symbols");
+    write_symbol_table(Path::new(&kernel_path), &symbols_path);
+    disk_builder.set_ramdisk(symbols_path);
+
     disk_builder.create_uefi_image(&uefi_path).unwrap();
     disk_builder.create_bios_image(&bios_path).unwrap();
 
     println!("cargo:rustc-env=UEFI_IMAGE={}", uefi_path.display());
     println!("cargo:rustc-env=BIOS_IMAGE={}", bios_path.display());
-}
\ No newline at end of file
+}
+
+/// Has to match `kernel::backtrace::symbols`.
+const SYMBOLS_MAGIC: &[u8; 8] = b"POPCSYMS";
+
+/// Writes every function symbol in the kernel ELF, sorted by address, in the format
+/// `kernel::backtrace::symbols` reads: the magic, a u64 count, then that many 24 byte entries
+/// (u64 address, u32 size, u32 name offset, u32 name length, 4 bytes padding) and the names.
+fn write_symbol_table(kernel: &Path, out: &Path) {
+    let bytes = fs::read(kernel).unwrap();
+    let elf = ElfFile::new(&bytes).unwrap();
+    let symtab = elf
+        .find_section_by_name(".symtab")
+        .expect("the kernel has no symbol table, was it stripped?");
+    let SectionData::SymbolTable64(entries) = symtab.get_data(&elf).unwrap() else {
+        panic!("the kernel's .symtab isn't a 64 bit symbol table");
+    };
+
+    let mut functions: Vec<(u64, u64, String)> = entries
+        .iter()
+        .filter(|entry| entry.get_type() == Ok(Type::Func) && entry.value() != 0)
+        .filter_map(|entry| {
+            let name = entry.get_name(&elf).ok()?;
+            // `{:#}` leaves the hash off
+            let name = format!("{:#}", rustc_demangle::demangle(name));
+            Some((entry.value(), entry.size(), name))
+        })
+        .collect();
+    functions.sort_by_key(|&(address, _, _)| address);
+    functions.dedup_by_key(|(address, _, _)| *address);
+
+    let mut table = Vec::new();
+    let mut names = Vec::new();
+    table.extend_from_slice(SYMBOLS_MAGIC);
+    table.extend_from_slice(&(functions.len() as u64).to_le_bytes());
+    for (address, size, name) in &functions {
+        table.extend_from_slice(&address.to_le_bytes());
+        table.extend_from_slice(&(*size as u32).to_le_bytes());
+        table.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        table.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        table.extend_from_slice(&[0; 4]);
+        names.extend_from_slice(name.as_bytes());
+    }
+    table.extend_from_slice(&names);
+    fs::write(out, table).unwrap();
+}
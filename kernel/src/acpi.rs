@@ -0,0 +1,446 @@
+//! Finding and reading ACPI tables.
+//!
+//! The tables sit in normal RAM (reclaimable or NVS), so they're read straight through the
+//! physical memory mapping.
+
+use conquer_once::spin::OnceCell;
+use core::fmt;
+use x86_64::PhysAddr;
+use x86_64::instructions::port::Port;
+
+use crate::memory;
+
+pub mod aml;
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+use fadt::Fadt;
+use hpet::Hpet;
+use madt::Madt;
+use mcfg::Mcfg;
+
+/// The Root System Description Pointer, revision 2 layout. Revision 0 ends after `rsdt_address`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+const RSDP_V1_SIZE: usize = 20;
+
+/// The header every system description table starts with.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+pub const SDT_HEADER_SIZE: usize = size_of::<SdtHeader>();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The bootloader didn't find an RSDP.
+    NoRsdp,
+    /// The RSDP's signature or checksum is wrong.
+    InvalidRsdp,
+    /// The RSDT or XSDT has the wrong signature or checksum.
+    InvalidRootTable,
+    /// `init` hasn't been called, or failed.
+    Uninitalized,
+    TableNotFound([u8; 4]),
+    /// The table is there, but its contents don't make sense.
+    InvalidTable([u8; 4]),
+}
+
+impl fmt::Display for AcpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AcpiError::NoRsdp => write!(f, "no RSDP"),
+            AcpiError::InvalidRsdp => write!(f, "invalid RSDP"),
+            AcpiError::InvalidRootTable => write!(f, "invalid RSDT/XSDT"),
+            AcpiError::Uninitalized => write!(f, "ACPI not initalized"),
+            AcpiError::TableNotFound(signature) => {
+                write!(f, "no {} table", Signature(signature))
+            }
+            AcpiError::InvalidTable(signature) => {
+                write!(f, "invalid {} table", Signature(signature))
+            }
+        }
+    }
+}
+
+/// Prints a table signature as text.
+struct Signature<'a>(&'a [u8; 4]);
+
+impl fmt::Display for Signature<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &byte in self.0 {
+            write!(f, "{}", byte as char)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a register lives, as the tables describe it (the Generic Address Structure).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    /// 1 to 4 for byte to qword accesses, 0 for "whatever fits".
+    pub access_size: u8,
+    pub address: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfig,
+    Other(u8),
+}
+
+pub const GENERIC_ADDRESS_SIZE: usize = 12;
+
+impl GenericAddress {
+    /// Parses the structure at `offset`, `None` if it's cut off or all zero (not present).
+    pub(crate) fn parse(bytes: &[u8], offset: usize) -> Option<GenericAddress> {
+        let address = read_u64(bytes, offset + 4)?;
+        if address == 0 {
+            return None;
+        }
+        Some(GenericAddress {
+            space: match read_u8(bytes, offset)? {
+                0 => AddressSpace::SystemMemory,
+                1 => AddressSpace::SystemIo,
+                2 => AddressSpace::PciConfig,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: read_u8(bytes, offset + 1)?,
+            bit_offset: read_u8(bytes, offset + 2)?,
+            access_size: read_u8(bytes, offset + 3)?,
+            address,
+        })
+    }
+
+    /// A register in I/O space, for the pre ACPI 2.0 fields that are plain port numbers.
+    pub(crate) fn io_port(port: u32, length: u8) -> Option<GenericAddress> {
+        (port != 0).then_some(GenericAddress {
+            space: AddressSpace::SystemIo,
+            bit_width: length * 8,
+            bit_offset: 0,
+            access_size: 0,
+            address: u64::from(port),
+        })
+    }
+
+    /// Reads the register.
+    ///
+    /// This function is unsafe because reading a device register can have side effects.
+    pub unsafe fn read(&self) -> u64 {
+        let value = match self.space {
+            AddressSpace::SystemMemory => {
+                let ptr = (memory::physical_memory_offset() + self.address).as_ptr::<u8>();
+                unsafe {
+                    match self.width() {
+                        8 => u64::from(ptr.read_volatile()),
+                        16 => u64::from(ptr.cast::<u16>().read_volatile()),
+                        32 => u64::from(ptr.cast::<u32>().read_volatile()),
+                        _ => ptr.cast::<u64>().read_volatile(),
+                    }
+                }
+            }
+            AddressSpace::SystemIo => {
+                let port = self.address as u16;
+                unsafe {
+                    match self.width() {
+                        8 => u64::from(Port::<u8>::new(port).read()),
+                        16 => u64::from(Port::<u16>::new(port).read()),
+                        _ => u64::from(Port::<u32>::new(port).read()),
+                    }
+                }
+            }
+            AddressSpace::PciConfig => {
+                let port = unsafe { select_pci_config(self.address) };
+                unsafe {
+                    match self.width() {
+                        8 => u64::from(Port::<u8>::new(port).read()),
+                        16 => u64::from(Port::<u16>::new(port).read()),
+                        _ => u64::from(Port::<u32>::new(port).read()),
+                    }
+                }
+            }
+            AddressSpace::Other(_) => 0,
+        };
+        value >> self.bit_offset
+    }
+
+    /// Writes `value` to the register. Writes to address spaces we don't know are dropped.
+    ///
+    /// This function is unsafe because the caller must make sure writing the register doesn't
+    /// break memory safety, it's usually something like a reset or power control.
+    pub unsafe fn write(&self, value: u64) {
+        let value = value << self.bit_offset;
+        match self.space {
+            AddressSpace::SystemMemory => {
+                let ptr = (memory::physical_memory_offset() + self.address).as_mut_ptr::<u8>();
+                unsafe {
+                    match self.width() {
+                        8 => ptr.write_volatile(value as u8),
+                        16 => ptr.cast::<u16>().write_volatile(value as u16),
+                        32 => ptr.cast::<u32>().write_volatile(value as u32),
+                        _ => ptr.cast::<u64>().write_volatile(value),
+                    }
+                }
+            }
+            AddressSpace::SystemIo => {
+                let port = self.address as u16;
+                unsafe {
+                    match self.width() {
+                        8 => Port::<u8>::new(port).write(value as u8),
+                        16 => Port::<u16>::new(port).write(value as u16),
+                        _ => Port::<u32>::new(port).write(value as u32),
+                    }
+                }
+            }
+            AddressSpace::PciConfig => {
+                let port = unsafe { select_pci_config(self.address) };
+                unsafe {
+                    match self.width() {
+                        8 => Port::<u8>::new(port).write(value as u8),
+                        16 => Port::<u16>::new(port).write(value as u16),
+                        _ => Port::<u32>::new(port).write(value as u32),
+                    }
+                }
+            }
+            AddressSpace::Other(_) => {}
+        }
+    }
+
+    /// Access width in bits, from `access_size` if it's given.
+    fn width(&self) -> u8 {
+        match self.access_size {
+            1..=4 => 8 << (self.access_size - 1),
+            _ => self.bit_width.next_power_of_two().clamp(8, 64),
+        }
+    }
+}
+
+/// Selects the PCI config register a GAS points at (device, function and offset packed into
+/// the address, always segment and bus 0) with the legacy 0xcf8 mechanism, and returns the data
+/// port to access it through.
+unsafe fn select_pci_config(address: u64) -> u16 {
+    let device = (address >> 32) as u32 & 0x1f;
+    let function = (address >> 16) as u32 & 0x7;
+    let offset = address as u32 & 0xff;
+    unsafe {
+        Port::<u32>::new(0xcf8).write(1 << 31 | device << 11 | function << 8 | offset & 0xfc)
+    };
+    0xcfc + (offset & 3) as u16
+}
+
+impl fmt::Display for GenericAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.space {
+            AddressSpace::SystemMemory => write!(f, "mem {:#x}", self.address),
+            AddressSpace::SystemIo => write!(f, "port {:#x}", self.address),
+            AddressSpace::PciConfig => write!(f, "pci {:#x}", self.address),
+            AddressSpace::Other(space) => write!(f, "space {} {:#x}", space, self.address),
+        }
+    }
+}
+
+/// The RSDT or XSDT, whichever the RSDP points to.
+#[derive(Debug, Clone, Copy)]
+struct RootTable {
+    address: PhysAddr,
+    /// 4 byte entries for the RSDT, 8 for the XSDT.
+    entry_size: usize,
+    entries: usize,
+}
+
+static ROOT_TABLE: OnceCell<RootTable> = OnceCell::uninit();
+
+/// Validates the RSDP at `rsdp_addr` and the root table it points to.
+///
+/// This function is unsafe because the caller must make sure `rsdp_addr` is what the bootloader
+/// reported, and that `memory::init` has been called.
+pub unsafe fn init(rsdp_addr: Option<u64>) -> Result<(), AcpiError> {
+    let rsdp_addr = PhysAddr::new(rsdp_addr.ok_or(AcpiError::NoRsdp)?);
+    let rsdp: Rsdp = unsafe { phys_ptr::<Rsdp>(rsdp_addr).read_unaligned() };
+    if &rsdp.signature != b"RSD PTR " || !checksum_ok(rsdp_addr, RSDP_V1_SIZE) {
+        return Err(AcpiError::InvalidRsdp);
+    }
+
+    let (address, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        if !checksum_ok(rsdp_addr, rsdp.length as usize) {
+            return Err(AcpiError::InvalidRsdp);
+        }
+        (PhysAddr::new(rsdp.xsdt_address), 8)
+    } else {
+        (PhysAddr::new(u64::from(rsdp.rsdt_address)), 4)
+    };
+
+    let header = header_at(address);
+    let signature = if entry_size == 8 { b"XSDT" } else { b"RSDT" };
+    if &header.signature != signature || !checksum_ok(address, header.length as usize) {
+        return Err(AcpiError::InvalidRootTable);
+    }
+
+    ROOT_TABLE.init_once(|| RootTable {
+        address,
+        entry_size,
+        entries: (header.length as usize - SDT_HEADER_SIZE) / entry_size,
+    });
+    Ok(())
+}
+
+/// The physical addresses of every table the root table lists.
+pub fn tables() -> Result<impl Iterator<Item = PhysAddr>, AcpiError> {
+    let root = *ROOT_TABLE.get().ok_or(AcpiError::Uninitalized)?;
+    Ok((0..root.entries).map(move |i| {
+        let entry = root.address + (SDT_HEADER_SIZE + i * root.entry_size) as u64;
+        let address = if root.entry_size == 8 {
+            unsafe { phys_ptr::<u64>(entry).read_unaligned() }
+        } else {
+            u64::from(unsafe { phys_ptr::<u32>(entry).read_unaligned() })
+        };
+        PhysAddr::new(address)
+    }))
+}
+
+/// Finds the table with `signature`, skipping any copy with a bad checksum.
+pub fn find_table(signature: &[u8; 4]) -> Result<PhysAddr, AcpiError> {
+    tables()?
+        .find(|&address| {
+            let header = header_at(address);
+            &header.signature == signature && checksum_ok(address, header.length as usize)
+        })
+        .ok_or(AcpiError::TableNotFound(*signature))
+}
+
+/// The header of the table at `address`.
+pub fn header_at(address: PhysAddr) -> SdtHeader {
+    unsafe { phys_ptr::<SdtHeader>(address).read_unaligned() }
+}
+
+/// The whole table at `address`, header included.
+pub fn table_bytes(address: PhysAddr) -> &'static [u8] {
+    let length = header_at(address).length as usize;
+    unsafe { core::slice::from_raw_parts(phys_ptr(address), length) }
+}
+
+/// ACPI checksums make all bytes of a structure sum up to 0.
+fn checksum_ok(address: PhysAddr, length: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(phys_ptr::<u8>(address), length) };
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+fn phys_ptr<T>(address: PhysAddr) -> *const T {
+    (memory::physical_memory_offset() + address.as_u64()).as_ptr()
+}
+
+/// Logs the tables the firmware provides and what the kernel makes of them.
+pub fn log_summary() {
+    let Ok(tables) = tables() else {
+        log::warn!("ACPI: not initalized");
+        return;
+    };
+    for address in tables {
+        let header = header_at(address);
+        let (length, revision) = (header.length, header.revision);
+        log::info!(
+            "ACPI: {} at {:#x}, {} bytes, revision {}",
+            Signature(&header.signature),
+            address.as_u64(),
+            length,
+            revision
+        );
+    }
+
+    match Madt::get() {
+        Ok(madt) => log::info!(
+            "ACPI: MADT: {} CPU(s), {} I/O APIC(s), {} override(s), local APIC at {:#x}",
+            madt.processors.iter().filter(|p| p.enabled).count(),
+            madt.io_apics.len(),
+            madt.overrides.len(),
+            madt.local_apic_address.as_u64()
+        ),
+        Err(err) => log::warn!("ACPI: {err}"),
+    }
+    match Fadt::get() {
+        Ok(fadt) => log::info!(
+            "ACPI: FADT: SCI IRQ {}, PM1a control {:?}, reset {:?}, century register {}",
+            fadt.sci_interrupt,
+            fadt.pm1a_control_block.map(|gas| gas.address),
+            fadt.reset_register.map(|gas| gas.address),
+            fadt.century
+        ),
+        Err(err) => log::warn!("ACPI: {err}"),
+    }
+    match Hpet::get() {
+        Ok(hpet) => log::info!(
+            "ACPI: HPET: at {:#x}, {} comparator(s), {} bit counter",
+            hpet.base_address.as_u64(),
+            hpet.comparators,
+            if hpet.counter_64bit { 64 } else { 32 }
+        ),
+        Err(err) => log::info!("ACPI: {err}"),
+    }
+    match Mcfg::get() {
+        Ok(mcfg) => {
+            for region in &mcfg.regions {
+                log::info!(
+                    "ACPI: MCFG: segment {} buses {}..={} at {:#x}",
+                    region.segment,
+                    region.bus_start,
+                    region.bus_end,
+                    region.base_address.as_u64()
+                );
+            }
+        }
+        // the i440FX (QEMU's pc machine) has no PCI express
+        Err(err) => log::info!("ACPI: {err}"),
+    }
+}
+
+/// Little endian field readers for table parsing, `None` if the field runs past the end.
+pub(crate) fn read_u8(bytes: &[u8], offset: usize) -> Option<u8> {
+    bytes.get(offset).copied()
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
@@ -0,0 +1,85 @@
+//! Just enough AML to pull constants out of the DSDT, there's no interpreter.
+
+const NAME_OP: u8 = 0x08;
+const ROOT_PREFIX: u8 = b'\\';
+const PACKAGE_OP: u8 = 0x12;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const ONES_OP: u8 = 0xff;
+const BYTE_PREFIX: u8 = 0x0a;
+const WORD_PREFIX: u8 = 0x0b;
+const DWORD_PREFIX: u8 = 0x0c;
+
+/// The SLP_TYPa/SLP_TYPb values for a sleep state, which go into the PM1a/PM1b control
+/// registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SleepType {
+    pub a: u16,
+    pub b: u16,
+}
+
+/// Finds `Name(\_Sx, Package() { SLP_TYPa, SLP_TYPb, ... })` for sleep state `state` (0 to 5)
+/// in `aml`, which is a DSDT or SSDT without its header.
+///
+/// This only works when the package is a plain constant, if firmware builds it at runtime
+/// (or hides it in a method) this returns `None`.
+pub fn sleep_type(aml: &[u8], state: u8) -> Option<SleepType> {
+    let name = [b'_', b'S', b'0' + state, b'_'];
+    let mut start = 0;
+    while let Some(found) = find(&aml[start..], &name) {
+        let at = start + found;
+        start = at + 1;
+        let declared = match at {
+            0 => false,
+            1 => aml[0] == NAME_OP,
+            _ => aml[at - 1] == NAME_OP || (aml[at - 1] == ROOT_PREFIX && aml[at - 2] == NAME_OP),
+        };
+        if !declared || aml.get(at + 4) != Some(&PACKAGE_OP) {
+            continue; // a reference to it, or something else with the same bytes
+        }
+        if let Some(sleep_type) = parse_package(&aml[at + 5..]) {
+            return Some(sleep_type);
+        }
+    }
+    None
+}
+
+/// Parses the package after its PackageOp: PkgLength, NumElements, then the first two elements.
+fn parse_package(bytes: &[u8]) -> Option<SleepType> {
+    let lead = *bytes.first()?;
+    // bits 6-7 of the lead byte say how many more length bytes follow, we just skip them
+    let length_bytes = usize::from(lead >> 6);
+    let mut rest = bytes.get(1 + length_bytes..)?;
+    let elements = *rest.first()?;
+    if elements < 2 {
+        return None;
+    }
+    rest = &rest[1..];
+
+    let (a, rest) = integer(rest)?;
+    let (b, _) = integer(rest)?;
+    Some(SleepType {
+        a: a as u16,
+        b: b as u16,
+    })
+}
+
+/// Decodes an integer constant, returning it and the bytes after it.
+fn integer(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let (&op, rest) = bytes.split_first()?;
+    match op {
+        ZERO_OP => Some((0, rest)),
+        ONE_OP => Some((1, rest)),
+        ONES_OP => Some((u32::MAX, rest)),
+        BYTE_PREFIX => Some((u32::from(*rest.first()?), rest.get(1..)?)),
+        WORD_PREFIX => Some((u32::from(super::read_u16(rest, 0)?), rest.get(2..)?)),
+        DWORD_PREFIX => Some((super::read_u32(rest, 0)?, rest.get(4..)?)),
+        _ => None,
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -0,0 +1,127 @@
+use x86_64::PhysAddr;
+
+use super::{
+    AcpiError, GENERIC_ADDRESS_SIZE, GenericAddress, find_table, read_u8, read_u16, read_u32,
+    read_u64, table_bytes,
+};
+
+const SIGNATURE: &[u8; 4] = b"FACP";
+
+// field offsets, the X_ ones only exist since ACPI 2.0
+const DSDT: usize = 40;
+const SCI_INT: usize = 46;
+const SMI_CMD: usize = 48;
+const ACPI_ENABLE: usize = 52;
+const ACPI_DISABLE: usize = 53;
+const PM1A_EVT_BLK: usize = 56;
+const PM1B_EVT_BLK: usize = 60;
+const PM1A_CNT_BLK: usize = 64;
+const PM1B_CNT_BLK: usize = 68;
+const PM_TMR_BLK: usize = 76;
+const PM1_EVT_LEN: usize = 88;
+const PM1_CNT_LEN: usize = 89;
+const PM_TMR_LEN: usize = 91;
+const CENTURY: usize = 108;
+const IAPC_BOOT_ARCH: usize = 109;
+const FLAGS: usize = 112;
+const RESET_REG: usize = 116;
+const RESET_VALUE: usize = 128;
+const X_DSDT: usize = 140;
+const X_PM1A_EVT_BLK: usize = 148;
+const X_PM1B_EVT_BLK: usize = X_PM1A_EVT_BLK + GENERIC_ADDRESS_SIZE;
+const X_PM1A_CNT_BLK: usize = X_PM1B_EVT_BLK + GENERIC_ADDRESS_SIZE;
+const X_PM1B_CNT_BLK: usize = X_PM1A_CNT_BLK + GENERIC_ADDRESS_SIZE;
+const X_PM_TMR_BLK: usize = X_PM1B_CNT_BLK + 2 * GENERIC_ADDRESS_SIZE;
+
+// FLAGS bits
+const TMR_VAL_EXT: u32 = 1 << 8;
+const RESET_REG_SUP: u32 = 1 << 10;
+// IAPC_BOOT_ARCH bits
+const BOOT_ARCH_8042: u16 = 1 << 1;
+
+/// The Fixed ACPI Description Table: where the power management registers are and the DSDT.
+///
+/// Registers are taken from the 64 bit `X_` fields when the table has them, the old port
+/// numbers otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    pub revision: u8,
+    pub dsdt: PhysAddr,
+    /// The ISA IRQ the SCI arrives on.
+    pub sci_interrupt: u16,
+    /// Port taking `acpi_enable`/`acpi_disable` to switch ACPI mode, `None` if it's always on.
+    pub smi_command_port: Option<u32>,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event_block: Option<GenericAddress>,
+    pub pm1b_event_block: Option<GenericAddress>,
+    pub pm1a_control_block: Option<GenericAddress>,
+    pub pm1b_control_block: Option<GenericAddress>,
+    pub pm_timer_block: Option<GenericAddress>,
+    /// The PM timer counts with 32 bits instead of 24.
+    pub pm_timer_32bit: bool,
+    /// Where `reset_value` goes to reset the machine, if it supports that.
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+    /// The CMOS RTC register holding the century, 0 if there isn't one.
+    pub century: u8,
+    /// There's an 8042 keyboard controller.
+    pub has_8042: bool,
+    pub flags: u32,
+}
+
+impl Fadt {
+    /// Finds and parses the FADT.
+    pub fn get() -> Result<Fadt, AcpiError> {
+        parse(table_bytes(find_table(SIGNATURE)?)).ok_or(AcpiError::InvalidTable(*SIGNATURE))
+    }
+}
+
+fn parse(bytes: &[u8]) -> Option<Fadt> {
+    let revision = read_u8(bytes, 8)?;
+    let flags = read_u32(bytes, FLAGS)?;
+    // ACPI 1.0 tables stop before these, and a zero X_ field means "use the old one"
+    let extended = |offset| GenericAddress::parse(bytes, offset);
+    let port = |offset, length_offset| {
+        GenericAddress::io_port(read_u32(bytes, offset)?, read_u8(bytes, length_offset)?)
+    };
+    let pm1_event_length = read_u8(bytes, PM1_EVT_LEN)? / 2;
+
+    let dsdt = match read_u64(bytes, X_DSDT) {
+        Some(address) if address != 0 => address,
+        _ => u64::from(read_u32(bytes, DSDT)?),
+    };
+    let reset_register = if flags & RESET_REG_SUP != 0 {
+        extended(RESET_REG)
+    } else {
+        None
+    };
+
+    Some(Fadt {
+        revision,
+        dsdt: PhysAddr::new(dsdt),
+        sci_interrupt: read_u16(bytes, SCI_INT)?,
+        smi_command_port: Some(read_u32(bytes, SMI_CMD)?).filter(|&port| port != 0),
+        acpi_enable: read_u8(bytes, ACPI_ENABLE)?,
+        acpi_disable: read_u8(bytes, ACPI_DISABLE)?,
+        // the event blocks are status then enable registers, each half the length
+        pm1a_event_block: extended(X_PM1A_EVT_BLK)
+            .or_else(|| GenericAddress::io_port(read_u32(bytes, PM1A_EVT_BLK)?, pm1_event_length)),
+        pm1b_event_block: extended(X_PM1B_EVT_BLK)
+            .or_else(|| GenericAddress::io_port(read_u32(bytes, PM1B_EVT_BLK)?, pm1_event_length)),
+        pm1a_control_block: extended(X_PM1A_CNT_BLK).or_else(|| port(PM1A_CNT_BLK, PM1_CNT_LEN)),
+        pm1b_control_block: extended(X_PM1B_CNT_BLK).or_else(|| port(PM1B_CNT_BLK, PM1_CNT_LEN)),
+        pm_timer_block: extended(X_PM_TMR_BLK).or_else(|| port(PM_TMR_BLK, PM_TMR_LEN)),
+        pm_timer_32bit: flags & TMR_VAL_EXT != 0,
+        reset_value: if reset_register.is_some() {
+            read_u8(bytes, RESET_VALUE)?
+        } else {
+            0
+        },
+        reset_register,
+        century: read_u8(bytes, CENTURY)?,
+        // revision 1 tables don't have the field, and those machines all had one
+        has_8042: revision < 2 || read_u16(bytes, IAPC_BOOT_ARCH)? & BOOT_ARCH_8042 != 0,
+        flags,
+    })
+}
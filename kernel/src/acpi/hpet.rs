@@ -0,0 +1,50 @@
+use x86_64::PhysAddr;
+
+use super::{
+    AcpiError, AddressSpace, GenericAddress, SDT_HEADER_SIZE, find_table, read_u8, read_u16,
+    read_u32, table_bytes,
+};
+
+const SIGNATURE: &[u8; 4] = b"HPET";
+
+/// The High Precision Event Timer Description Table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hpet {
+    pub hardware_revision: u8,
+    /// How many comparators (timers) the first block has.
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    /// The timers can take over IRQ 0 and 8 from the PIT and RTC.
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+    /// The MMIO registers, always in system memory.
+    pub base_address: PhysAddr,
+    pub number: u8,
+    /// The smallest period, in counter ticks, that periodic mode can do without losing interrupts.
+    pub minimum_tick: u16,
+}
+
+impl Hpet {
+    /// Finds and parses the HPET table.
+    pub fn get() -> Result<Hpet, AcpiError> {
+        parse(table_bytes(find_table(SIGNATURE)?)).ok_or(AcpiError::InvalidTable(*SIGNATURE))
+    }
+}
+
+fn parse(bytes: &[u8]) -> Option<Hpet> {
+    let block_id = read_u32(bytes, SDT_HEADER_SIZE)?;
+    let base = GenericAddress::parse(bytes, SDT_HEADER_SIZE + 4)?;
+    if base.space != AddressSpace::SystemMemory {
+        return None;
+    }
+    Some(Hpet {
+        hardware_revision: block_id as u8,
+        comparators: ((block_id >> 8) & 0x1f) as u8 + 1,
+        counter_64bit: block_id & (1 << 13) != 0,
+        legacy_replacement: block_id & (1 << 15) != 0,
+        pci_vendor_id: (block_id >> 16) as u16,
+        base_address: PhysAddr::new(base.address),
+        number: read_u8(bytes, SDT_HEADER_SIZE + 16)?,
+        minimum_tick: read_u16(bytes, SDT_HEADER_SIZE + 17)?,
+    })
+}
@@ -0,0 +1,178 @@
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use super::{
+    AcpiError, SDT_HEADER_SIZE, find_table, read_u8, read_u16, read_u32, read_u64, table_bytes,
+};
+
+const SIGNATURE: &[u8; 4] = b"APIC";
+
+/// The Multiple APIC Description Table: the CPUs, interrupt controllers and how the ISA IRQs are
+/// wired to them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    /// The machine also has the legacy 8259 PICs, which have to be masked when using the APICs.
+    pub has_8259: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+    pub local_apic_nmis: Vec<LocalApicNmi>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    /// Usable right away.
+    pub enabled: bool,
+    /// Not enabled, but could be brought online later.
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// The first global system interrupt this I/O APIC handles.
+    pub gsi_base: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// An ISA IRQ that isn't identity mapped to a global system interrupt, or doesn't use the ISA
+/// default of active high, edge triggered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub source_irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+/// A local APIC LINT pin wired to NMI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// `None` means every processor.
+    pub processor_uid: Option<u32>,
+    pub lint: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+impl Madt {
+    /// Finds and parses the MADT.
+    pub fn get() -> Result<Madt, AcpiError> {
+        parse(table_bytes(find_table(SIGNATURE)?)).ok_or(AcpiError::InvalidTable(*SIGNATURE))
+    }
+
+    /// The global system interrupt ISA `irq` arrives at, with its polarity and trigger mode.
+    pub fn isa_irq(&self, irq: u8) -> (u32, Polarity, TriggerMode) {
+        match self.overrides.iter().find(|o| o.source_irq == irq) {
+            Some(o) => (o.gsi, o.polarity, o.trigger),
+            None => (u32::from(irq), Polarity::ActiveHigh, TriggerMode::Edge),
+        }
+    }
+}
+
+fn parse(bytes: &[u8]) -> Option<Madt> {
+    let mut madt = Madt {
+        local_apic_address: PhysAddr::new(u64::from(read_u32(bytes, SDT_HEADER_SIZE)?)),
+        has_8259: read_u32(bytes, SDT_HEADER_SIZE + 4)? & 1 != 0,
+        processors: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+        local_apic_nmis: Vec::new(),
+    };
+
+    let mut offset = SDT_HEADER_SIZE + 8;
+    while offset + 2 <= bytes.len() {
+        let kind = read_u8(bytes, offset)?;
+        let length = usize::from(read_u8(bytes, offset + 1)?);
+        if length < 2 {
+            return None; // would loop forever
+        }
+        let entry = bytes.get(offset..offset + length)?;
+        match kind {
+            0 => {
+                let flags = read_u32(entry, 4)?;
+                madt.processors.push(Processor {
+                    processor_uid: u32::from(read_u8(entry, 2)?),
+                    apic_id: u32::from(read_u8(entry, 3)?),
+                    enabled: flags & 1 != 0,
+                    online_capable: flags & 2 != 0,
+                });
+            }
+            1 => madt.io_apics.push(IoApic {
+                id: read_u8(entry, 2)?,
+                address: PhysAddr::new(u64::from(read_u32(entry, 4)?)),
+                gsi_base: read_u32(entry, 8)?,
+            }),
+            2 => {
+                let (polarity, trigger) = decode_flags(read_u16(entry, 8)?);
+                madt.overrides.push(InterruptOverride {
+                    source_irq: read_u8(entry, 3)?,
+                    gsi: read_u32(entry, 4)?,
+                    polarity,
+                    trigger,
+                });
+            }
+            4 => {
+                let (polarity, trigger) = decode_flags(read_u16(entry, 3)?);
+                let uid = read_u8(entry, 2)?;
+                madt.local_apic_nmis.push(LocalApicNmi {
+                    processor_uid: (uid != 0xff).then_some(u32::from(uid)),
+                    lint: read_u8(entry, 5)?,
+                    polarity,
+                    trigger,
+                });
+            }
+            5 => madt.local_apic_address = PhysAddr::new(read_u64(entry, 4)?),
+            9 => {
+                let flags = read_u32(entry, 8)?;
+                madt.processors.push(Processor {
+                    processor_uid: read_u32(entry, 12)?,
+                    apic_id: read_u32(entry, 4)?,
+                    enabled: flags & 1 != 0,
+                    online_capable: flags & 2 != 0,
+                });
+            }
+            0xa => {
+                let (polarity, trigger) = decode_flags(read_u16(entry, 2)?);
+                let uid = read_u32(entry, 4)?;
+                madt.local_apic_nmis.push(LocalApicNmi {
+                    processor_uid: (uid != u32::MAX).then_some(uid),
+                    lint: read_u8(entry, 8)?,
+                    polarity,
+                    trigger,
+                });
+            }
+            _ => {} // interrupt source types we don't care about
+        }
+        offset += length;
+    }
+    Some(madt)
+}
+
+/// Decodes the MPS INTI flags, "conforms to the bus" meaning the ISA default.
+fn decode_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::ActiveHigh,
+    };
+    let trigger = match (flags >> 2) & 0b11 {
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::Edge,
+    };
+    (polarity, trigger)
+}
@@ -0,0 +1,69 @@
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use super::{AcpiError, SDT_HEADER_SIZE, find_table, read_u8, read_u16, read_u64, table_bytes};
+
+const SIGNATURE: &[u8; 4] = b"MCFG";
+/// 8 reserved bytes follow the header.
+const ENTRIES: usize = SDT_HEADER_SIZE + 8;
+const ENTRY_SIZE: usize = 16;
+
+/// The PCI Express memory mapped configuration space table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mcfg {
+    pub regions: Vec<PciConfigRegion>,
+}
+
+/// The configuration space of buses `bus_start..=bus_end` in one PCI segment, 4 KiB per function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciConfigRegion {
+    /// Where bus 0 would be, even if `bus_start` isn't 0.
+    pub base_address: PhysAddr,
+    pub segment: u16,
+    pub bus_start: u8,
+    pub bus_end: u8,
+}
+
+impl PciConfigRegion {
+    /// The configuration space of `bus:device.function`, `None` if this region doesn't cover it.
+    pub fn function_address(&self, bus: u8, device: u8, function: u8) -> Option<PhysAddr> {
+        if !(self.bus_start..=self.bus_end).contains(&bus) || device >= 32 || function >= 8 {
+            return None;
+        }
+        let offset = u64::from(bus) << 20 | u64::from(device) << 15 | u64::from(function) << 12;
+        Some(self.base_address + offset)
+    }
+}
+
+impl Mcfg {
+    /// Finds and parses the MCFG.
+    pub fn get() -> Result<Mcfg, AcpiError> {
+        parse(table_bytes(find_table(SIGNATURE)?)).ok_or(AcpiError::InvalidTable(*SIGNATURE))
+    }
+
+    /// The region covering `segment` and `bus`.
+    pub fn region(&self, segment: u16, bus: u8) -> Option<&PciConfigRegion> {
+        self.regions.iter().find(|region| {
+            region.segment == segment && (region.bus_start..=region.bus_end).contains(&bus)
+        })
+    }
+}
+
+fn parse(bytes: &[u8]) -> Option<Mcfg> {
+    let mut regions = Vec::new();
+    let mut offset = ENTRIES;
+    while offset + ENTRY_SIZE <= bytes.len() {
+        let region = PciConfigRegion {
+            base_address: PhysAddr::new(read_u64(bytes, offset)?),
+            segment: read_u16(bytes, offset + 8)?,
+            bus_start: read_u8(bytes, offset + 10)?,
+            bus_end: read_u8(bytes, offset + 11)?,
+        };
+        if region.bus_start > region.bus_end {
+            return None;
+        }
+        regions.push(region);
+        offset += ENTRY_SIZE;
+    }
+    Some(Mcfg { regions })
+}
@@ -1,27 +1,176 @@
+use crate::memory;
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::null_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked-list",
+    feature = "alloc-slab"
+)))]
 use fixed_size_block::FixedSizeBlockAllocator;
+use stats::AllocatorStats;
 use x86_64::VirtAddr;
 use x86_64::instructions::interrupts;
 use x86_64::structures::paging::mapper::MapToError;
-use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB, mapped up front
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB, the heap never grows past this
+const HEAP_GROW_STEP: usize = 64 * 1024; // grow by at least this much at a time
+
+/// Where the kernel heap currently ends, 0 until `init_heap`. Heaps that end anywhere else (the
+/// ones tests build on their own buffers) never grow, nothing after them is ours to map.
+static KERNEL_HEAP_END: AtomicUsize = AtomicUsize::new(0);
 
 pub mod bump;
+#[cfg(feature = "heap-debug")]
+pub mod debug;
 pub mod fixed_size_block;
+pub mod linked_list;
+pub mod oom;
+pub mod slab;
+pub mod stats;
+
+/// The allocator behind `#[global_allocator]`, picked by the `alloc-*` cargo features.
+///
+/// `alloc-fixed-block` is the default. If more than one is enabled the first of `alloc-bump`,
+/// `alloc-linked-list` and `alloc-slab` wins, so e.g. `--features alloc-slab` works without
+/// also passing `--no-default-features`.
+#[cfg(feature = "alloc-bump")]
+type HeapAllocator = bump::BumpAllocator;
+#[cfg(all(feature = "alloc-linked-list", not(feature = "alloc-bump")))]
+type HeapAllocator = linked_list::LinkedListAllocator;
+#[cfg(all(
+    feature = "alloc-slab",
+    not(any(feature = "alloc-bump", feature = "alloc-linked-list"))
+))]
+type HeapAllocator = slab::SlabAllocator;
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked-list",
+    feature = "alloc-slab"
+)))]
+type HeapAllocator = FixedSizeBlockAllocator;
+
+static ALLOCATOR: Locked<HeapAllocator> = Locked::new(HeapAllocator::new());
+
+/// Passes everything on to `ALLOCATOR`, and hands failed allocations to `oom::handle` once the
+/// allocator's lock is released again, so pressure callbacks can free memory.
+struct GlobalHeap;
 
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static GLOBAL_HEAP: GlobalHeap = GlobalHeap;
+
+unsafe impl GlobalAlloc for GlobalHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { ALLOCATOR.alloc(layout) };
+        if !ptr.is_null() {
+            return ptr;
+        }
+        oom::handle(layout, || unsafe { ALLOCATOR.alloc(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { ALLOCATOR.dealloc(ptr, layout) }
+    }
+}
+
+/// Maps the initial `HEAP_SIZE` bytes at `HEAP_START` and hands them to the global allocator.
+///
+/// Must be called after `memory::init`.
+pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
+    map_heap_pages(
+        HEAP_START,
+        HEAP_SIZE,
+        &mut *memory::mapper(),
+        &mut *memory::frame_allocator(),
+    )?;
+
+    interrupts::without_interrupts(|| unsafe {
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    });
+    KERNEL_HEAP_END.store(HEAP_START + HEAP_SIZE, Ordering::Relaxed);
+
+    log::info!("Heap initalized!");
+    Ok(())
+}
+
+/// Snapshot of the global allocator's usage counters.
+pub fn stats() -> AllocatorStats {
+    interrupts::without_interrupts(|| ALLOCATOR.lock().stats())
+}
+
+/// Prints the global allocator's usage counters over serial.
+pub fn dump_stats() {
+    crate::serial_println!("allocator stats:\n{}", stats());
+}
 
-pub fn init_heap(
+/// Allocates from `heap`, growing it with `grow_heap` if it is full.
+fn alloc_or_grow(heap: &mut linked_list_allocator::Heap, layout: Layout) -> *mut u8 {
+    if let Ok(ptr) = heap.allocate_first_fit(layout) {
+        return ptr.as_ptr();
+    }
+
+    // worst case the new memory has to make up for the alignment too
+    let grown = grow_heap(heap.top() as usize, layout.size() + layout.align());
+    if grown == 0 {
+        return null_mut();
+    }
+    unsafe { heap.extend(grown) };
+
+    match heap.allocate_first_fit(layout) {
+        Ok(ptr) => ptr.as_ptr(),
+        Err(_) => null_mut(),
+    }
+}
+
+/// Maps at least `min_size` more bytes of heap, starting at the current end of the heap `heap_end`.
+///
+/// Only the kernel heap grows, see `KERNEL_HEAP_END`.
+///
+/// Returns the number of bytes actually mapped, always a multiple of the page size. This is 0
+/// when the heap already hit `HEAP_MAX_SIZE` or the mapper is busy, and can be less than asked
+/// for when frames run out.
+fn grow_heap(heap_end: usize, min_size: usize) -> usize {
+    // an empty heap ends at 0 too
+    if heap_end == 0 || heap_end != KERNEL_HEAP_END.load(Ordering::Relaxed) {
+        return 0;
+    }
+    let room = (HEAP_START + HEAP_MAX_SIZE).saturating_sub(heap_end);
+    let size = align_up(min_size.max(HEAP_GROW_STEP), 4096).min(room);
+    if size == 0 {
+        return 0;
+    }
+
+    // we're inside an allocation, so spinning on a lock held further up the stack would never end
+    let Some((mut mapper, mut frame_allocator)) = memory::try_lock() else {
+        return 0;
+    };
+
+    let mut mapped = 0;
+    while mapped < size {
+        if map_heap_pages(heap_end + mapped, 4096, &mut *mapper, &mut *frame_allocator).is_err() {
+            break;
+        }
+        mapped += 4096;
+    }
+
+    KERNEL_HEAP_END.store(heap_end + mapped, Ordering::Relaxed);
+    mapped
+}
+
+fn map_heap_pages(
+    start: usize,
+    size: usize,
     mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
 ) -> Result<(), MapToError<Size4KiB>> {
     let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE.try_into().unwrap() - 1u64;
+        let heap_start = VirtAddr::new(start as u64);
+        let heap_end = heap_start + size.try_into().unwrap() - 1u64;
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(heap_start_page, heap_end_page)
@@ -32,14 +181,16 @@ pub fn init_heap(
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
         let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(err) => {
+                // the page never got the frame, so nothing else will free it
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                return Err(err);
+            }
+        }
     }
 
-    interrupts::without_interrupts(|| unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
-    });
-
-    log::info!("Heap initalized!");
     Ok(())
 }
 
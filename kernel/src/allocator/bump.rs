@@ -1,12 +1,20 @@
-use core::{alloc::GlobalAlloc, ptr};
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    marker::PhantomData,
+    ptr::{self, NonNull},
+};
 
-use super::{Locked, align_up};
+use super::{
+    Locked, align_up, grow_heap,
+    stats::{AllocatorStats, UsageCounters},
+};
 
 pub struct BumpAllocator {
     heap_start: usize,
     heap_end: usize,
     next: usize,
     allocation_count: usize,
+    counters: UsageCounters,
 }
 
 impl BumpAllocator {
@@ -16,6 +24,7 @@ impl BumpAllocator {
             heap_end: 0,
             next: 0,
             allocation_count: 0,
+            counters: UsageCounters::new(),
         }
     }
 
@@ -29,32 +38,139 @@ impl BumpAllocator {
         self.heap_end = heap_start + heap_size;
         self.next = heap_start;
     }
+
+    /// Snapshot of the usage counters.
+    pub fn stats(&self) -> AllocatorStats {
+        let heap_size = self.heap_end - self.heap_start;
+        self.counters.snapshot(heap_size, self.heap_end - self.next)
+    }
+
+    /// Bumps `next` past an allocation for `layout`, returning where it starts. `None` if it
+    /// doesn't fit.
+    fn bump(&mut self, layout: Layout) -> Option<usize> {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = alloc_start.checked_add(layout.size())?;
+        if alloc_end > self.heap_end {
+            return None;
+        }
+        self.next = alloc_end;
+        Some(alloc_start)
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut bump = self.lock();
 
-        let alloc_start = align_up(bump.next, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return ptr::null_mut(),
-        };
-
-        if alloc_end > bump.heap_end {
-            ptr::null_mut() // out of memory
-        } else {
-            bump.next = alloc_end;
-            bump.allocation_count += 1;
-            alloc_start as *mut u8
+        let mut alloc_start = bump.bump(layout);
+        if alloc_start.is_none() {
+            let needed = align_up(bump.next, layout.align())
+                .saturating_add(layout.size())
+                .saturating_sub(bump.heap_end);
+            let grown = grow_heap(bump.heap_end, needed);
+            bump.heap_end += grown;
+            alloc_start = bump.bump(layout);
+        }
+
+        match alloc_start {
+            Some(alloc_start) => {
+                bump.allocation_count += 1;
+                bump.counters.record_alloc(None, &layout);
+                alloc_start as *mut u8
+            }
+            None => ptr::null_mut(), // out of memory
         }
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock();
+        bump.counters.record_dealloc(None, &layout);
         bump.allocation_count -= 1;
         if bump.allocation_count == 0 {
             bump.next = bump.heap_start;
         }
     }
 }
+
+/// A bump allocator over a region the caller hands in, meant as the `Allocator` of short-lived
+/// collections:
+///
+/// ```ignore
+/// let mut scratch = [0u8; 4096];
+/// let mut arena = BumpArena::new(&mut scratch);
+/// let mut tokens = Vec::new_in(&arena);
+/// tokens.push(Box::new_in(token, &arena));
+/// ```
+///
+/// Freeing only gives memory back if it was the most recent allocation, everything else stays
+/// used until `reset` or `reset_to`. Those take `&mut self`, which the borrow checker only hands
+/// out once every `Box` and `Vec` using the arena is gone.
+pub struct BumpArena<'a> {
+    bump: Locked<BumpAllocator>,
+    _region: PhantomData<&'a mut [u8]>,
+}
+
+/// A position in a `BumpArena` to go back to with `reset_to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaMark(usize);
+
+impl<'a> BumpArena<'a> {
+    pub fn new(region: &'a mut [u8]) -> Self {
+        let mut bump = BumpAllocator::new();
+        // the region is borrowed for as long as the arena lives, so nobody else touches it
+        unsafe { bump.init(region.as_mut_ptr() as usize, region.len()) };
+        BumpArena {
+            bump: Locked::new(bump),
+            _region: PhantomData,
+        }
+    }
+
+    /// Remembers how much of the arena is in use right now.
+    pub fn mark(&self) -> ArenaMark {
+        ArenaMark(self.bump.lock().next)
+    }
+
+    /// Frees everything allocated since `mark` was taken.
+    pub fn reset_to(&mut self, mark: ArenaMark) {
+        let bump = self.bump.inner.get_mut();
+        assert!(
+            (bump.heap_start..=bump.next).contains(&mark.0),
+            "mark is not from this arena, or from before a reset"
+        );
+        bump.next = mark.0;
+    }
+
+    /// Frees everything.
+    pub fn reset(&mut self) {
+        let bump = self.bump.inner.get_mut();
+        bump.next = bump.heap_start;
+    }
+
+    /// Bytes in use, including alignment padding.
+    pub fn used(&self) -> usize {
+        let bump = self.bump.lock();
+        bump.next - bump.heap_start
+    }
+
+    /// Bytes left at the end of the arena.
+    pub fn remaining(&self) -> usize {
+        let bump = self.bump.lock();
+        bump.heap_end - bump.next
+    }
+}
+
+unsafe impl Allocator for BumpArena<'_> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let alloc_start = self.bump.lock().bump(layout).ok_or(AllocError)?;
+        let ptr = NonNull::new(ptr::with_exposed_provenance_mut(alloc_start)).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut bump = self.bump.lock();
+        // only the latest allocation can be undone, the rest waits for a reset
+        if ptr.as_ptr() as usize + layout.size() == bump.next {
+            bump.next = ptr.as_ptr() as usize;
+        }
+    }
+}
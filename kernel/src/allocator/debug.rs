@@ -0,0 +1,96 @@
+//! The checks `FixedSizeBlockAllocator` does when built with the `heap-debug` feature.
+//!
+//! Freed blocks are filled with `FREE_POISON` and checked again when they get handed out, which
+//! catches writes through dangling pointers. Allocations from the fallback heap get a red zone of
+//! `RED_ZONE_CANARY` bytes on both sides, checked on free to catch overflows. Double frees are
+//! caught by looking for the block on its free list. Everything found gets reported over serial.
+
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::serial_println;
+
+/// Freed blocks are filled with this, past the free list link at their start.
+pub const FREE_POISON: u8 = 0x6b;
+/// New allocations are filled with this, so reads of uninitalized memory stand out.
+pub const ALLOC_POISON: u8 = 0xa5;
+/// What the red zones around fallback allocations are filled with.
+pub const RED_ZONE_CANARY: u8 = 0xfd;
+pub const RED_ZONE_SIZE: usize = 16;
+
+static CORRUPTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// How many problems have been reported so far.
+pub fn corruptions() -> usize {
+    CORRUPTIONS.load(Ordering::Relaxed)
+}
+
+pub(super) fn report(problem: &str, addr: usize, layout: &Layout) {
+    CORRUPTIONS.fetch_add(1, Ordering::Relaxed);
+    serial_println!(
+        "heap-debug: {} at {:#x} (size {}, align {})",
+        problem,
+        addr,
+        layout.size(),
+        layout.align()
+    );
+}
+
+/// Fills `len` bytes at `start` with `FREE_POISON`.
+///
+/// This function is unsafe because the caller must own the memory.
+pub(super) unsafe fn poison(start: *mut u8, len: usize) {
+    unsafe { start.write_bytes(FREE_POISON, len) };
+}
+
+/// Checks that the `len` bytes at `start` still hold `FREE_POISON`, returning the offset of the
+/// first one that doesn't.
+///
+/// This function is unsafe because the caller must own the memory.
+pub(super) unsafe fn find_overwritten(start: *const u8, len: usize) -> Option<usize> {
+    let bytes = unsafe { core::slice::from_raw_parts(start, len) };
+    bytes.iter().position(|&byte| byte != FREE_POISON)
+}
+
+/// The layout to ask the fallback heap for so `layout` fits between two red zones, and the offset
+/// of the allocation in it. The front red zone is padded up to the alignment.
+pub(super) fn padded_layout(layout: &Layout) -> Option<(Layout, usize)> {
+    let front = layout.align().max(RED_ZONE_SIZE);
+    let size = front
+        .checked_add(layout.size())?
+        .checked_add(RED_ZONE_SIZE)?;
+    let padded = Layout::from_size_align(size, layout.align()).ok()?;
+    Some((padded, front))
+}
+
+/// Fills the red zones around the allocation at `ptr`.
+///
+/// This function is unsafe because `ptr` must come from a `padded_layout` allocation for `layout`.
+pub(super) unsafe fn arm_red_zones(ptr: *mut u8, layout: &Layout) {
+    unsafe {
+        ptr.sub(RED_ZONE_SIZE)
+            .write_bytes(RED_ZONE_CANARY, RED_ZONE_SIZE);
+        ptr.add(layout.size())
+            .write_bytes(RED_ZONE_CANARY, RED_ZONE_SIZE);
+    }
+}
+
+/// Reports any red zone byte around `ptr` that got overwritten.
+///
+/// This function is unsafe for the same reasons as `arm_red_zones`.
+pub(super) unsafe fn check_red_zones(ptr: *const u8, layout: &Layout) {
+    let (before, after) = unsafe {
+        (
+            core::slice::from_raw_parts(ptr.sub(RED_ZONE_SIZE), RED_ZONE_SIZE),
+            core::slice::from_raw_parts(ptr.add(layout.size()), RED_ZONE_SIZE),
+        )
+    };
+    if before.iter().any(|&byte| byte != RED_ZONE_CANARY) {
+        report("buffer underflow (front red zone)", ptr as usize, layout);
+    }
+    if after.iter().any(|&byte| byte != RED_ZONE_CANARY) {
+        report("buffer overflow (back red zone)", ptr as usize, layout);
+    }
+}
@@ -3,18 +3,24 @@ use core::{
     ptr::{self, NonNull},
 };
 
-use super::Locked;
+#[cfg(feature = "heap-debug")]
+use super::debug;
+use super::{
+    Locked, alloc_or_grow,
+    stats::{AllocatorStats, UsageCounters},
+};
 
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
 
 /// sizes must be power of two as they are also used as the block alignment (which must be powers of two)
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 256, 512, 1024, 2048];
 
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    counters: UsageCounters,
 }
 
 impl FixedSizeBlockAllocator {
@@ -24,6 +30,7 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            counters: UsageCounters::new(),
         }
     }
 
@@ -37,23 +44,92 @@ impl FixedSizeBlockAllocator {
         }
     }
 
-    /// Allocates using the fallback allocator.
+    /// Snapshot of the usage counters.
+    pub fn stats(&self) -> AllocatorStats {
+        self.counters.snapshot(
+            self.fallback_allocator.size(),
+            self.fallback_allocator.free(),
+        )
+    }
+
+    /// Allocates using the fallback allocator, growing the heap if it is full.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => ptr::null_mut(),
+        alloc_or_grow(&mut self.fallback_allocator, layout)
+    }
+
+    /// Allocates a layout too big for any block size from the fallback allocator.
+    #[cfg(not(feature = "heap-debug"))]
+    fn large_alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.fallback_alloc(layout)
+    }
+
+    #[cfg(not(feature = "heap-debug"))]
+    unsafe fn large_dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let ptr = NonNull::new(ptr).unwrap();
+        unsafe { self.fallback_allocator.deallocate(ptr, layout) };
+    }
+
+    /// Same as without `heap-debug`, but the allocation sits between two red zones.
+    #[cfg(feature = "heap-debug")]
+    fn large_alloc(&mut self, layout: Layout) -> *mut u8 {
+        let Some((padded, front)) = debug::padded_layout(&layout) else {
+            return ptr::null_mut();
+        };
+        let base = self.fallback_alloc(padded);
+        if base.is_null() {
+            return base;
+        }
+        unsafe {
+            let ptr = base.add(front);
+            debug::arm_red_zones(ptr, &layout);
+            ptr
+        }
+    }
+
+    #[cfg(feature = "heap-debug")]
+    unsafe fn large_dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (padded, front) = debug::padded_layout(&layout).unwrap();
+        unsafe {
+            debug::check_red_zones(ptr, &layout);
+            let base = NonNull::new(ptr.sub(front)).unwrap();
+            self.fallback_allocator.deallocate(base, padded);
+        }
+    }
+
+    /// Whether `ptr` is already on the free list of size class `index`.
+    #[cfg(feature = "heap-debug")]
+    fn is_free(&self, index: usize, ptr: *mut u8) -> bool {
+        let mut node = self.list_heads[index].as_deref();
+        while let Some(current) = node {
+            if ptr::eq(current, ptr as *const ListNode) {
+                return true;
+            }
+            node = current.next.as_deref();
         }
+        false
     }
 }
 
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let index = list_index(&layout);
+        let ptr = match index {
             Some(index) => match allocator.list_heads[index].take() {
                 Some(node) => {
                     allocator.list_heads[index] = node.next.take();
-                    node as *mut ListNode as *mut u8
+                    allocator.counters.free_blocks[index] -= 1;
+                    let ptr = node as *mut ListNode as *mut u8;
+                    #[cfg(feature = "heap-debug")]
+                    unsafe {
+                        let header = size_of::<ListNode>();
+                        let poisoned = BLOCK_SIZES[index] - header;
+                        if let Some(offset) = debug::find_overwritten(ptr.add(header), poisoned) {
+                            let addr = ptr as usize + header + offset;
+                            debug::report("write after free", addr, &layout);
+                        }
+                    }
+                    ptr
                 }
                 None => {
                     let block_size = BLOCK_SIZES[index];
@@ -64,29 +140,48 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                     }
                 }
             },
-            None => allocator.fallback_alloc(layout),
+            None => allocator.large_alloc(layout),
+        };
+        if !ptr.is_null() {
+            allocator.counters.record_alloc(index, &layout);
+            #[cfg(feature = "heap-debug")]
+            unsafe {
+                ptr.write_bytes(debug::ALLOC_POISON, layout.size())
+            };
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let index = list_index(&layout);
+        #[cfg(feature = "heap-debug")]
+        if let Some(index) = index
+            && allocator.is_free(index, ptr)
+        {
+            // freeing it again would put it on the list twice and hand it out twice
+            debug::report("double free", ptr as usize, &layout);
+            return;
+        }
+        allocator.counters.record_dealloc(index, &layout);
+        match index {
             Some(index) => {
+                allocator.counters.free_blocks[index] += 1;
                 let node = ListNode {
                     next: allocator.list_heads[index].take(),
                 };
                 let node_ptr = ptr as *mut ListNode;
                 unsafe {
+                    #[cfg(feature = "heap-debug")]
+                    debug::poison(
+                        ptr.add(size_of::<ListNode>()),
+                        BLOCK_SIZES[index] - size_of::<ListNode>(),
+                    );
                     node_ptr.write(node);
                     allocator.list_heads[index] = Some(&mut *node_ptr);
                 }
             }
-            None => {
-                let ptr = NonNull::new(ptr).unwrap();
-                unsafe {
-                    allocator.fallback_allocator.deallocate(ptr, layout);
-                }
-            }
+            None => unsafe { allocator.large_dealloc(ptr, layout) },
         }
     }
 }
@@ -94,7 +189,7 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
 /// Pick a fitting block size for the given layout.
 ///
 /// Returns an index into the `BLOCK_SIZES` array.
-fn list_index(layout: &Layout) -> Option<usize> {
+pub(super) fn list_index(layout: &Layout) -> Option<usize> {
     let required_block_size = layout.size().max(layout.align());
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
@@ -0,0 +1,59 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::{self, NonNull},
+};
+
+use super::{
+    Locked, alloc_or_grow,
+    stats::{AllocatorStats, UsageCounters},
+};
+
+/// `linked_list_allocator::Heap` on its own, every allocation walks the free list.
+pub struct LinkedListAllocator {
+    heap: linked_list_allocator::Heap,
+    counters: UsageCounters,
+}
+
+impl LinkedListAllocator {
+    /// Creates an empty LinkedListAllocator
+    pub const fn new() -> Self {
+        LinkedListAllocator {
+            heap: linked_list_allocator::Heap::empty(),
+            counters: UsageCounters::new(),
+        }
+    }
+
+    /// Initalize the allocator with the given heap bounds
+    ///
+    /// This function is unsafe because the caller must ensure that the given
+    /// heap bounds are valid, and the heap is unused. Must only be called once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe {
+            self.heap
+                .init(ptr::with_exposed_provenance_mut(heap_start), heap_size);
+        }
+    }
+
+    /// Snapshot of the usage counters.
+    pub fn stats(&self) -> AllocatorStats {
+        self.counters.snapshot(self.heap.size(), self.heap.free())
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let ptr = alloc_or_grow(&mut allocator.heap, layout);
+        if !ptr.is_null() {
+            allocator.counters.record_alloc(None, &layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        let ptr = NonNull::new(ptr).unwrap();
+        unsafe { allocator.heap.deallocate(ptr, layout) };
+        allocator.counters.record_dealloc(None, &layout);
+    }
+}
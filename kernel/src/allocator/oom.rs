@@ -0,0 +1,73 @@
+//! What happens when the global allocator comes back empty handed.
+//!
+//! The layout and the allocator's stats are logged first, then every registered memory pressure
+//! callback gets a go at freeing something, with a retry (which grows the heap if it can) after
+//! each. If all that fails the null pointer goes back to the caller. For infallible allocations
+//! that means `handle_alloc_error` panics right after, fallible ones just get their error.
+
+use core::{
+    alloc::Layout,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use spin::Mutex;
+
+/// Called when memory runs out, with the layout that didn't fit. Should free whatever it can
+/// spare (caches, preallocated buffers).
+pub type PressureCallback = fn(&Layout);
+
+const MAX_CALLBACKS: usize = 8;
+
+// a fixed array, registering can't be allowed to allocate on the way to an out of memory path
+static CALLBACKS: Mutex<[Option<PressureCallback>; MAX_CALLBACKS]> =
+    Mutex::new([None; MAX_CALLBACKS]);
+/// Set while the callbacks run, if they run out of memory themselves they don't get called again.
+static IN_CALLBACKS: AtomicBool = AtomicBool::new(false);
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// Adds a callback to run when the heap is exhausted.
+///
+/// Panics if `MAX_CALLBACKS` are registered already.
+pub fn register_pressure_callback(callback: PressureCallback) {
+    let mut callbacks = CALLBACKS.lock();
+    let slot = callbacks
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many memory pressure callbacks");
+    *slot = Some(callback);
+}
+
+/// How many allocations failed for good, even after the callbacks ran.
+pub fn failures() -> usize {
+    FAILURES.load(Ordering::Relaxed)
+}
+
+/// Logs why `layout` didn't fit and tries to make room for it, calling `retry` to allocate it
+/// again. Returns what the retry got, or null.
+pub(super) fn handle(layout: Layout, retry: impl Fn() -> *mut u8) -> *mut u8 {
+    // only a warning, `try_reserve` and friends end up here too and can cope
+    log::warn!(
+        "heap exhausted: could not allocate {} bytes (align {})\n{}",
+        layout.size(),
+        layout.align(),
+        super::stats()
+    );
+
+    if !IN_CALLBACKS.swap(true, Ordering::Acquire) {
+        // copied out so the callbacks can register more callbacks without deadlocking
+        let callbacks = *CALLBACKS.lock();
+        for callback in callbacks.iter().flatten() {
+            callback(&layout);
+            let ptr = retry();
+            if !ptr.is_null() {
+                IN_CALLBACKS.store(false, Ordering::Release);
+                log::warn!("memory pressure callbacks made room for {layout:?}");
+                return ptr;
+            }
+        }
+        IN_CALLBACKS.store(false, Ordering::Release);
+    }
+
+    FAILURES.fetch_add(1, Ordering::Relaxed);
+    ptr::null_mut()
+}
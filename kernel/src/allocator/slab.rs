@@ -0,0 +1,301 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::size_of,
+    ptr::{self, NonNull},
+};
+
+use super::{
+    Locked, align_up, alloc_or_grow,
+    fixed_size_block::{BLOCK_SIZES, list_index},
+    stats::{AllocatorStats, UsageCounters},
+};
+
+/// Slabs are at least a page, and big enough to hold this many objects of their class.
+const MIN_SLAB_SIZE: usize = 4096;
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// How many empty slabs each size class keeps around before handing them back to the fallback
+/// heap, so a single alloc/free pair on a class boundary doesn't create and destroy a slab.
+const EMPTY_SLABS_KEPT: usize = 1;
+
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SlabState {
+    Empty,
+    Partial,
+    Full,
+}
+
+/// Header at the start of every slab, the objects follow right after it.
+struct Slab {
+    prev: Option<NonNull<Slab>>,
+    next: Option<NonNull<Slab>>,
+    free_objects: Option<NonNull<FreeObject>>,
+    free_count: usize,
+    capacity: usize,
+    state: SlabState,
+}
+
+impl Slab {
+    fn state(&self) -> SlabState {
+        if self.free_count == self.capacity {
+            SlabState::Empty
+        } else if self.free_count == 0 {
+            SlabState::Full
+        } else {
+            SlabState::Partial
+        }
+    }
+}
+
+/// An intrusive doubly linked list of slabs.
+struct SlabList {
+    head: Option<NonNull<Slab>>,
+    len: usize,
+}
+
+impl SlabList {
+    const fn new() -> Self {
+        SlabList { head: None, len: 0 }
+    }
+
+    unsafe fn push(&mut self, mut slab: NonNull<Slab>) {
+        unsafe {
+            slab.as_mut().prev = None;
+            slab.as_mut().next = self.head;
+            if let Some(mut head) = self.head {
+                head.as_mut().prev = Some(slab);
+            }
+        }
+        self.head = Some(slab);
+        self.len += 1;
+    }
+
+    unsafe fn remove(&mut self, slab: NonNull<Slab>) {
+        unsafe {
+            let Slab { prev, next, .. } = *slab.as_ptr();
+            match prev {
+                Some(mut prev) => prev.as_mut().next = next,
+                None => self.head = next,
+            }
+            if let Some(mut next) = next {
+                next.as_mut().prev = prev;
+            }
+        }
+        self.len -= 1;
+    }
+}
+
+struct SizeClass {
+    empty: SlabList,
+    partial: SlabList,
+    full: SlabList,
+}
+
+impl SizeClass {
+    const fn new() -> Self {
+        SizeClass {
+            empty: SlabList::new(),
+            partial: SlabList::new(),
+            full: SlabList::new(),
+        }
+    }
+
+    fn list(&mut self, state: SlabState) -> &mut SlabList {
+        match state {
+            SlabState::Empty => &mut self.empty,
+            SlabState::Partial => &mut self.partial,
+            SlabState::Full => &mut self.full,
+        }
+    }
+}
+
+/// A slab allocator using the same size classes as `FixedSizeBlockAllocator`.
+///
+/// Each size class owns slabs carved out of the fallback heap, sorted into empty, partial and
+/// full lists. Allocations come from partial slabs first, so empty ones can be handed back to
+/// the fallback heap and reused for any other size.
+pub struct SlabAllocator {
+    classes: [SizeClass; BLOCK_SIZES.len()],
+    fallback_allocator: linked_list_allocator::Heap,
+    counters: UsageCounters,
+}
+
+// the raw slab pointers all point into the heap owned by the allocator
+unsafe impl Send for SlabAllocator {}
+
+impl SlabAllocator {
+    /// Creates an empty SlabAllocator
+    pub const fn new() -> Self {
+        const EMPTY: SizeClass = SizeClass::new();
+        SlabAllocator {
+            classes: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: linked_list_allocator::Heap::empty(),
+            counters: UsageCounters::new(),
+        }
+    }
+
+    /// Initalize the allocator with the given heap bounds
+    ///
+    /// This function is unsafe because the caller must ensure that the given
+    /// heap bounds are valid, and the heap is unused. Must only be called once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe {
+            self.fallback_allocator
+                .init(ptr::with_exposed_provenance_mut(heap_start), heap_size);
+        }
+    }
+
+    /// Snapshot of the usage counters.
+    pub fn stats(&self) -> AllocatorStats {
+        self.counters.snapshot(
+            self.fallback_allocator.size(),
+            self.fallback_allocator.free(),
+        )
+    }
+
+    fn alloc_object(&mut self, index: usize) -> *mut u8 {
+        let class = &mut self.classes[index];
+        let slab = match class.partial.head.or(class.empty.head) {
+            Some(slab) => slab,
+            None => match self.new_slab(index) {
+                Some(slab) => slab,
+                None => return ptr::null_mut(),
+            },
+        };
+
+        unsafe {
+            let header = slab.as_ptr();
+            let object = (*header)
+                .free_objects
+                .expect("slab on a non-full list has no free objects");
+            (*header).free_objects = (*object.as_ptr()).next;
+            (*header).free_count -= 1;
+            self.update_state(index, slab);
+            self.counters.free_blocks[index] -= 1;
+            object.as_ptr() as *mut u8
+        }
+    }
+
+    unsafe fn dealloc_object(&mut self, index: usize, ptr: *mut u8) {
+        // slabs are aligned to their size, so masking the object address finds the header
+        let slab_addr = ptr as usize & !(slab_size(index) - 1);
+        let slab = NonNull::new(slab_addr as *mut Slab).unwrap();
+        let object = ptr as *mut FreeObject;
+
+        unsafe {
+            let header = slab.as_ptr();
+            object.write(FreeObject {
+                next: (*header).free_objects,
+            });
+            (*header).free_objects = NonNull::new(object);
+            (*header).free_count += 1;
+            self.counters.free_blocks[index] += 1;
+            self.update_state(index, slab);
+
+            if (*header).state == SlabState::Empty
+                && self.classes[index].empty.len > EMPTY_SLABS_KEPT
+            {
+                self.release_slab(index, slab);
+            }
+        }
+    }
+
+    /// Carves a new slab for size class `index` out of the fallback heap, and puts it on the
+    /// empty list.
+    fn new_slab(&mut self, index: usize) -> Option<NonNull<Slab>> {
+        let block_size = BLOCK_SIZES[index];
+        let slab_ptr = alloc_or_grow(&mut self.fallback_allocator, slab_layout(index)) as *mut Slab;
+        let slab = NonNull::new(slab_ptr)?;
+
+        let first_object = slab_ptr as usize + align_up(size_of::<Slab>(), block_size);
+        let capacity = (slab_ptr as usize + slab_size(index) - first_object) / block_size;
+
+        // thread the free list through the objects, back to front so it hands them out in order
+        let mut free_objects = None;
+        for i in (0..capacity).rev() {
+            let object = (first_object + i * block_size) as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free_objects }) };
+            free_objects = NonNull::new(object);
+        }
+
+        unsafe {
+            slab_ptr.write(Slab {
+                prev: None,
+                next: None,
+                free_objects,
+                free_count: capacity,
+                capacity,
+                state: SlabState::Empty,
+            });
+            self.classes[index].empty.push(slab);
+        }
+        self.counters.free_blocks[index] += capacity;
+        Some(slab)
+    }
+
+    /// Unlinks an empty slab and gives its memory back to the fallback heap.
+    unsafe fn release_slab(&mut self, index: usize, slab: NonNull<Slab>) {
+        unsafe {
+            let capacity = (*slab.as_ptr()).capacity;
+            self.classes[index].empty.remove(slab);
+            self.counters.free_blocks[index] -= capacity;
+            self.fallback_allocator
+                .deallocate(slab.cast(), slab_layout(index));
+        }
+    }
+
+    /// Moves the slab to the list matching its free count, if that changed.
+    unsafe fn update_state(&mut self, index: usize, slab: NonNull<Slab>) {
+        unsafe {
+            let header = slab.as_ptr();
+            let (old, new) = ((*header).state, (*header).state());
+            if old != new {
+                self.classes[index].list(old).remove(slab);
+                self.classes[index].list(new).push(slab);
+                (*header).state = new;
+            }
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<SlabAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let index = list_index(&layout);
+        let ptr = match index {
+            Some(index) => allocator.alloc_object(index),
+            None => alloc_or_grow(&mut allocator.fallback_allocator, layout),
+        };
+        if !ptr.is_null() {
+            allocator.counters.record_alloc(index, &layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        let index = list_index(&layout);
+        allocator.counters.record_dealloc(index, &layout);
+        match index {
+            Some(index) => unsafe { allocator.dealloc_object(index, ptr) },
+            None => {
+                let ptr = NonNull::new(ptr).unwrap();
+                unsafe {
+                    allocator.fallback_allocator.deallocate(ptr, layout);
+                }
+            }
+        }
+    }
+}
+
+fn slab_size(index: usize) -> usize {
+    (BLOCK_SIZES[index] * MIN_OBJECTS_PER_SLAB).max(MIN_SLAB_SIZE)
+}
+
+fn slab_layout(index: usize) -> Layout {
+    Layout::from_size_align(slab_size(index), slab_size(index)).unwrap()
+}
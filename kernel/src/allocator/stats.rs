@@ -0,0 +1,177 @@
+use core::{alloc::Layout, fmt};
+
+use super::fixed_size_block::BLOCK_SIZES;
+
+/// Usage of a single `BLOCK_SIZES` class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeClassStats {
+    pub block_size: usize,
+    /// Blocks currently handed out.
+    pub allocated: usize,
+    /// Blocks sitting on the free list, waiting to be reused.
+    pub free: usize,
+}
+
+/// A snapshot of the global allocator's counters, see `allocator::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorStats {
+    pub size_classes: [SizeClassStats; BLOCK_SIZES.len()],
+    /// Bytes currently handed out straight from the fallback heap (layouts too big for any class).
+    pub fallback_bytes: usize,
+    /// Bytes managed by the fallback heap, including the blocks carved out for size classes.
+    /// For allocators without size classes this is just the heap.
+    pub heap_size: usize,
+    /// Bytes of the fallback heap that are not allocated.
+    pub heap_free: usize,
+    /// Allocations that have not been freed yet.
+    pub live_allocations: usize,
+    /// Bytes handed out right now, counting size class allocations as their full block size.
+    pub bytes_in_use: usize,
+    /// The highest `bytes_in_use` has ever been.
+    pub peak_bytes_in_use: usize,
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>6} {:>10} {:>10}", "class", "allocated", "free")?;
+        for class in &self.size_classes {
+            writeln!(
+                f,
+                "{:>6} {:>10} {:>10}",
+                class.block_size, class.allocated, class.free
+            )?;
+        }
+        writeln!(f, "fallback: {} bytes", self.fallback_bytes)?;
+        writeln!(
+            f,
+            "heap: {} of {} bytes free",
+            self.heap_free, self.heap_size
+        )?;
+        write!(
+            f,
+            "live allocations: {}, in use: {} bytes, peak: {} bytes",
+            self.live_allocations, self.bytes_in_use, self.peak_bytes_in_use
+        )
+    }
+}
+
+/// The counters behind `AllocatorStats`, kept up to date by the allocators as they go.
+pub(super) struct UsageCounters {
+    pub allocated_blocks: [usize; BLOCK_SIZES.len()],
+    pub free_blocks: [usize; BLOCK_SIZES.len()],
+    fallback_bytes: usize,
+    live_allocations: usize,
+    bytes_in_use: usize,
+    peak_bytes_in_use: usize,
+}
+
+impl UsageCounters {
+    pub const fn new() -> Self {
+        UsageCounters {
+            allocated_blocks: [0; BLOCK_SIZES.len()],
+            free_blocks: [0; BLOCK_SIZES.len()],
+            fallback_bytes: 0,
+            live_allocations: 0,
+            bytes_in_use: 0,
+            peak_bytes_in_use: 0,
+        }
+    }
+
+    /// Counts an allocation served from size class `index`, or from the fallback heap if `None`.
+    pub fn record_alloc(&mut self, index: Option<usize>, layout: &Layout) {
+        let bytes = match index {
+            Some(index) => {
+                self.allocated_blocks[index] += 1;
+                BLOCK_SIZES[index]
+            }
+            None => {
+                self.fallback_bytes += layout.size();
+                layout.size()
+            }
+        };
+        self.live_allocations += 1;
+        self.bytes_in_use += bytes;
+        self.peak_bytes_in_use = self.peak_bytes_in_use.max(self.bytes_in_use);
+    }
+
+    pub fn record_dealloc(&mut self, index: Option<usize>, layout: &Layout) {
+        let bytes = match index {
+            Some(index) => {
+                self.allocated_blocks[index] -= 1;
+                BLOCK_SIZES[index]
+            }
+            None => {
+                self.fallback_bytes -= layout.size();
+                layout.size()
+            }
+        };
+        self.live_allocations -= 1;
+        self.bytes_in_use -= bytes;
+    }
+
+    /// Combines the counters with the size of the allocator's heap and how much of it is free.
+    pub fn snapshot(&self, heap_size: usize, heap_free: usize) -> AllocatorStats {
+        AllocatorStats {
+            size_classes: core::array::from_fn(|index| SizeClassStats {
+                block_size: BLOCK_SIZES[index],
+                allocated: self.allocated_blocks[index],
+                free: self.free_blocks[index],
+            }),
+            fallback_bytes: self.fallback_bytes,
+            heap_size,
+            heap_free,
+            live_allocations: self.live_allocations,
+            bytes_in_use: self.bytes_in_use,
+            peak_bytes_in_use: self.peak_bytes_in_use,
+        }
+    }
+}
+
+/// Remembers the allocator's counters at one point, so leaks can be checked for later on.
+///
+/// ```ignore
+/// let checkpoint = Checkpoint::new();
+/// run_some_task();
+/// checkpoint.assert_no_leaks();
+/// ```
+pub struct Checkpoint {
+    stats: AllocatorStats,
+}
+
+impl Checkpoint {
+    pub fn new() -> Self {
+        Checkpoint {
+            stats: super::stats(),
+        }
+    }
+
+    /// Allocations made since the checkpoint minus the ones freed.
+    pub fn net_allocations(&self) -> isize {
+        super::stats().live_allocations as isize - self.stats.live_allocations as isize
+    }
+
+    /// Bytes allocated since the checkpoint minus the ones freed.
+    pub fn net_bytes(&self) -> isize {
+        super::stats().bytes_in_use as isize - self.stats.bytes_in_use as isize
+    }
+
+    /// Panics if anything allocated since the checkpoint is still alive.
+    pub fn assert_no_leaks(&self) {
+        let now = super::stats();
+        if now.live_allocations != self.stats.live_allocations {
+            panic!(
+                "{} allocations ({} bytes) leaked since checkpoint\nthen:\n{}\nnow:\n{}",
+                self.net_allocations(),
+                self.net_bytes(),
+                self.stats,
+                now
+            );
+        }
+    }
+}
+
+impl Default for Checkpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,155 @@
+//! Stack backtraces, by walking the chain of saved frame pointers.
+//!
+//! Everything is built with `force-frame-pointers` (see .cargo/config.toml), so every function
+//! starts with `push rbp; mov rbp, rsp`: `[rbp]` is the caller's RBP and `[rbp + 8]` the return
+//! address into the caller. core and alloc come precompiled without frame pointers, so their
+//! frames just don't show up.
+
+use core::arch::asm;
+use core::fmt;
+use x86_64::VirtAddr;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::memory;
+
+pub mod symbols;
+
+/// Stops a corrupt chain that loops back on itself.
+const MAX_FRAMES: usize = 64;
+
+/// A return address found on the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Where this frame's saved RBP is.
+    pub frame_pointer: VirtAddr,
+    pub return_address: VirtAddr,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the return address is just past the call, which can be the first byte of the next
+        // function if the call was the last instruction
+        write!(f, "{:#018x}", self.return_address.as_u64())?;
+        match symbols::resolve(self.return_address - 1u64) {
+            Some(symbol) => write!(f, " {}+{:#x}", symbol.name, symbol.offset + 1),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Iterator over the frames of a stack, innermost first.
+pub struct Frames {
+    rbp: u64,
+    remaining: usize,
+}
+
+impl Frames {
+    /// Walks the chain starting at the frame `rbp` points to.
+    pub fn from_frame_pointer(rbp: u64) -> Frames {
+        Frames {
+            rbp,
+            remaining: MAX_FRAMES,
+        }
+    }
+}
+
+impl Iterator for Frames {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let frame_pointer = VirtAddr::try_new(self.rbp).ok()?;
+        if frame_pointer.is_null() || !frame_pointer.is_aligned(8u64) {
+            return None;
+        }
+        let [saved_rbp, return_address] = unsafe { read(frame_pointer)? };
+        if return_address == 0 {
+            return None;
+        }
+
+        self.rbp = saved_rbp;
+        self.remaining -= 1;
+        Some(Frame {
+            frame_pointer,
+            return_address: VirtAddr::new_truncate(return_address),
+        })
+    }
+}
+
+/// Reads the `N` words at `addr`, if they're on a known stack or mapped.
+///
+/// This function is unsafe because the caller must ensure `addr` is 8 byte aligned.
+unsafe fn read<const N: usize>(addr: VirtAddr) -> Option<[u64; N]> {
+    let end = addr + (N * 8) as u64;
+    let on_stack = memory::stack::containing(addr).is_some_and(|stack| end <= stack.top);
+    let mapped = || {
+        memory::try_is_mapped(addr) == Some(true) && memory::try_is_mapped(end - 1u64) == Some(true)
+    };
+    if !on_stack && !mapped() {
+        return None;
+    }
+    Some(unsafe { addr.as_ptr::<[u64; N]>().read() })
+}
+
+/// The frames above whoever calls this.
+#[inline(always)]
+pub fn trace() -> Frames {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    Frames::from_frame_pointer(rbp)
+}
+
+/// The frames of the code an exception interrupted, so the exception handling machinery isn't in
+/// the way. Call from (something called by) the handler that got `frame`.
+///
+/// The handler's frame is the one with the RIP and CS the CPU pushed right above it. Depending on
+/// the error code and stack alignment there's up to two words in between. `None` if it isn't on
+/// the chain.
+pub fn interrupted(frame: &InterruptStackFrame) -> Option<Frames> {
+    let rip = frame.instruction_pointer.as_u64();
+    let cs = u64::from(frame.code_segment.0);
+    trace().find_map(|handler| {
+        let words = unsafe { read::<6>(handler.frame_pointer)? };
+        let pushed = (1..=3).any(|slot| words[slot] == rip && words[slot + 1] == cs);
+        pushed.then(|| Frames::from_frame_pointer(words[0]))
+    })
+}
+
+/// Logs `frames`, one per line with the function if the symbols are loaded.
+pub fn log(frames: impl Iterator<Item = Frame>) {
+    log::error!("Backtrace:");
+    for (i, frame) in frames.enumerate() {
+        log::error!("  #{i:<2} {frame}");
+    }
+}
+
+/// Logs where the exception happened and everything above it.
+pub fn log_interrupted(frame: &InterruptStackFrame) {
+    log::error!("Backtrace:");
+    log::error!(
+        "  #0  {} (faulting instruction)",
+        Address(frame.instruction_pointer)
+    );
+    let Some(frames) = interrupted(frame) else {
+        log::error!("  (the handler's frame isn't on the chain, can't go further)");
+        return;
+    };
+    for (i, frame) in frames.enumerate() {
+        log::error!("  #{:<2} {frame}", i + 1);
+    }
+}
+
+/// An address and the function it's in.
+pub struct Address(pub VirtAddr);
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x}", self.0.as_u64())?;
+        match symbols::resolve(self.0) {
+            Some(symbol) => write!(f, " {symbol}"),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! The kernel's function symbols, for turning addresses into names.
+//!
+//! `build.rs` pulls them out of the kernel ELF and hands them over as the ramdisk, sorted by
+//! address: the magic, a u64 count, then that many 24 byte entries (u64 address, u32 size, u32
+//! name offset, u32 name length, 4 bytes padding) followed by the names. Addresses are as linked,
+//! the kernel is position independent so they're off by `kernel_image_offset` at runtime.
+//!
+//! Tests boot through `bootimage runner`, which has no ramdisk, so they get bare addresses.
+
+use conquer_once::spin::OnceCell;
+use core::fmt;
+use x86_64::VirtAddr;
+
+const MAGIC: &[u8; 8] = b"POPCSYMS";
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 24;
+
+static SYMBOLS: OnceCell<SymbolTable> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolError {
+    NoRamdisk,
+    /// The ramdisk isn't a symbol table.
+    BadMagic,
+    /// The table claims more than there is.
+    Truncated,
+    AlreadyLoaded,
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::NoRamdisk => write!(f, "no ramdisk"),
+            SymbolError::BadMagic => write!(f, "the ramdisk isn't a symbol table"),
+            SymbolError::Truncated => write!(f, "the symbol table is truncated"),
+            SymbolError::AlreadyLoaded => write!(f, "symbols already loaded"),
+        }
+    }
+}
+
+/// A function an address resolved to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: &'static str,
+    /// Where the function starts, at runtime.
+    pub address: VirtAddr,
+    /// How far into the function the address is.
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+pub struct SymbolTable {
+    entries: &'static [u8],
+    names: &'static [u8],
+    image_offset: u64,
+}
+
+impl SymbolTable {
+    /// Checks the header and that every entry is there, the names only get checked on lookup.
+    pub fn parse(bytes: &'static [u8], image_offset: u64) -> Result<SymbolTable, SymbolError> {
+        if bytes.get(..MAGIC.len()) != Some(MAGIC) {
+            return Err(SymbolError::BadMagic);
+        }
+        let count = bytes
+            .get(MAGIC.len()..HEADER_SIZE)
+            .map(|count| u64::from_le_bytes(count.try_into().unwrap()))
+            .ok_or(SymbolError::Truncated)?;
+        let entries_end = usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(ENTRY_SIZE))
+            .and_then(|size| size.checked_add(HEADER_SIZE))
+            .filter(|&end| end <= bytes.len())
+            .ok_or(SymbolError::Truncated)?;
+
+        Ok(SymbolTable {
+            entries: &bytes[HEADER_SIZE..entries_end],
+            names: &bytes[entries_end..],
+            image_offset,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Address, size, name offset and name length of entry `i`.
+    fn entry(&self, i: usize) -> (u64, u64, usize, usize) {
+        let entry = &self.entries[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE];
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(entry[offset..offset + 4].try_into().unwrap());
+        (
+            u64::from_le_bytes(entry[..8].try_into().unwrap()),
+            u64::from(u32_at(8)),
+            u32_at(12) as usize,
+            u32_at(16) as usize,
+        )
+    }
+
+    /// The function `addr` is in.
+    pub fn resolve(&self, addr: VirtAddr) -> Option<Symbol> {
+        let linked = addr.as_u64().checked_sub(self.image_offset)?;
+        // the first entry starting after `linked`, the one before that is the candidate
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.entry(mid).0 <= linked {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let (start, size, name_offset, name_len) = self.entry(low.checked_sub(1)?);
+        let offset = linked - start;
+        // symbols from assembly often have no size, give them the benefit of the doubt
+        if size != 0 && offset >= size {
+            return None;
+        }
+
+        let name = self
+            .names
+            .get(name_offset..name_offset.checked_add(name_len)?)?;
+        Some(Symbol {
+            name: core::str::from_utf8(name).ok()?,
+            address: VirtAddr::new_truncate(start + self.image_offset),
+            offset,
+        })
+    }
+}
+
+/// Loads the symbol table from the ramdisk, returning how many symbols there are.
+///
+/// This function is unsafe because the caller must guarantee that `ramdisk_len` bytes are mapped
+/// at `ramdisk_addr` and stay that way, as the bootloader's `BootInfo` promises.
+pub unsafe fn init(
+    ramdisk_addr: Option<u64>,
+    ramdisk_len: u64,
+    kernel_image_offset: u64,
+) -> Result<usize, SymbolError> {
+    let ramdisk_addr = ramdisk_addr.ok_or(SymbolError::NoRamdisk)?;
+    let bytes =
+        unsafe { core::slice::from_raw_parts(ramdisk_addr as *const u8, ramdisk_len as usize) };
+    let table = SymbolTable::parse(bytes, kernel_image_offset)?;
+    let count = table.len();
+    SYMBOLS
+        .try_init_once(|| table)
+        .map_err(|_| SymbolError::AlreadyLoaded)?;
+    Ok(count)
+}
+
+/// The function `addr` is in, `None` if it's not in one or the symbols aren't loaded.
+pub fn resolve(addr: VirtAddr) -> Option<Symbol> {
+    SYMBOLS.try_get().ok()?.resolve(addr)
+}
@@ -1,3 +1,4 @@
+use crate::memory::stack;
 use lazy_static::lazy_static;
 use x86_64::registers::segmentation::{DS, ES, SS};
 use x86_64::VirtAddr;
@@ -5,20 +6,35 @@ use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector
 use x86_64::structures::tss::TaskStateSegment;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// An NMI can land anywhere, including halfway through switching stacks.
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+/// So a kernel stack overflow reaches the page fault handler, rather than faulting again trying to
+/// push the exception frame and ending up as a double fault. The catch is that page faults can't
+/// nest, the handler treats one inside itself as fatal.
+pub const PAGE_FAULT_IST_INDEX: u16 = 3;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE.try_into().unwrap();
-            stack_end
-        };
-        tss
-    };
+/// Every IST entry in use, and the name its stack is registered under.
+const IST_STACKS: [(u16, &str); 4] = [
+    (DOUBLE_FAULT_IST_INDEX, "double fault"),
+    (NMI_IST_INDEX, "nmi"),
+    (MACHINE_CHECK_IST_INDEX, "machine check"),
+    (PAGE_FAULT_IST_INDEX, "page fault"),
+];
+
+const IST_STACK_SIZE: usize = 4096 * 5;
+
+/// Mutable so `init_stacks` can swap the IST entries once memory is up, the CPU only reads them
+/// when an interrupt actually switches stacks.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// Used until `init_stacks` runs, as there's nowhere to map guarded stacks before that.
+fn boot_ist_stack(index: u16) -> VirtAddr {
+    static mut STACKS: [[u8; IST_STACK_SIZE]; IST_STACKS.len()] =
+        [[0; IST_STACK_SIZE]; IST_STACKS.len()];
+
+    let stack_start = VirtAddr::from_ptr(unsafe { &raw const STACKS[index as usize] });
+    stack_start + IST_STACK_SIZE.try_into().unwrap()
 }
 
 lazy_static! {
@@ -26,7 +42,8 @@ lazy_static! {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
         let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+        let tss = &raw const TSS;
+        let tss_selector = gdt.append(Descriptor::tss_segment(unsafe { &*tss }));
         (
             gdt,
             Selectors {
@@ -48,6 +65,10 @@ pub fn init() {
     use x86_64::instructions::segmentation::{CS, Segment};
     use x86_64::instructions::tables::load_tss;
 
+    for (index, _) in IST_STACKS {
+        set_ist_stack(index, boot_ist_stack(index));
+    }
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
@@ -57,3 +78,20 @@ pub fn init() {
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// Moves the IST stacks onto mapped stacks with guard pages, and registers the boot stack so its
+/// guard page is recognised too. Needs the heap, so call after `allocator::init_heap`.
+pub fn init_stacks() {
+    stack::register_current("kernel");
+
+    for (index, name) in IST_STACKS {
+        let stack = stack::allocate(name, IST_STACK_SIZE as u64)
+            .unwrap_or_else(|err| panic!("failed to map the {name} stack: {err:?}"));
+        set_ist_stack(index, stack.top);
+    }
+}
+
+fn set_ist_stack(index: u16, stack_top: VirtAddr) {
+    let tss = &raw mut TSS;
+    unsafe { (*tss).interrupt_stack_table[index as usize] = stack_top };
+}
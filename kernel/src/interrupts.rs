@@ -1,11 +1,16 @@
-use crate::{gdt, hlt_loop};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+pub mod apic;
+pub mod exceptions;
+pub mod irq;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+/// Where the local APIC sends spurious interrupts, which don't get an EOI.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
 
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
@@ -15,6 +20,8 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard, // PIC_1_OFFSET + 1, 33, auto incremented
+    Serial = PIC_1_OFFSET + 4, // COM1
+    Rtc = PIC_2_OFFSET,
 }
 
 impl InterruptIndex {
@@ -22,6 +29,11 @@ impl InterruptIndex {
         self as u8
     }
 
+    /// The ISA IRQ line, same numbering with the PICs and the I/O APIC.
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+
     fn _as_usize(self) -> usize {
         usize::from(self.as_u8())
     }
@@ -30,15 +42,9 @@ impl InterruptIndex {
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-        idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
+        exceptions::install(&mut idt);
+        irq::install(&mut idt);
+        idt[SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
@@ -47,48 +53,65 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    log::debug!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+/// Acknowledges the interrupt `index` with whichever controller delivered it.
+pub fn end_of_interrupt(index: InterruptIndex) {
+    end_of_vector(index.as_u8());
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    use x86_64::registers::control::Cr2;
-
-    log::error!("!!!!\nEXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}", Cr2::read(), error_code, stack_frame);
-    hlt_loop();
+fn end_of_vector(vector: u8) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        // if we send the wrong interrupt vector number, bad things happen (delete important interrupt or HANG the system)
+        unsafe { PICS.lock().notify_end_of_interrupt(vector) };
+    }
 }
 
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+/// The kernel's own handlers, which run before anything registered on the same line, `irq`
+/// sends the EOI after them.
+const BUILTIN_HANDLERS: [(InterruptIndex, fn()); 4] = [
+    (InterruptIndex::Timer, timer_interrupt),
+    (InterruptIndex::Keyboard, keyboard_interrupt),
+    (InterruptIndex::Serial, serial_interrupt),
+    (InterruptIndex::Rtc, rtc_interrupt),
+];
+
+fn builtin_handler(irq: u8) -> Option<fn()> {
+    BUILTIN_HANDLERS
+        .iter()
+        .find(|(index, _)| index.irq() == irq)
+        .map(|&(_, handler)| handler)
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+fn timer_interrupt() {
     //log::debug!(".");
-    unsafe {
-        // if we send the wrong interrupt vector number, bad things happen (delete important interrupt or HANG the system)
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+    crate::time::tick();
+    crate::task::timer::wake_expired();
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+fn keyboard_interrupt() {
     use x86_64::instructions::port::Port;
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
-    
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+}
+
+fn serial_interrupt() {
+    // unless a driver took the line, nothing reads serial input, drain it so the UART can raise
+    // the next one
+    if irq::has_handlers(InterruptIndex::Serial.irq()) {
+        return;
     }
+    let mut serial = crate::serial::SERIAL1.lock();
+    while serial.try_receive().is_ok() {}
 }
 
+fn rtc_interrupt() {
+    crate::time::rtc::handle_interrupt();
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
 #[test_case]
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3(); // int3 is the breakpoint instruction
@@ -0,0 +1,211 @@
+//! The local APIC and I/O APICs, which take over from the 8259 PICs once `init` finds them.
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::interrupts;
+
+use super::{InterruptIndex, PICS, SPURIOUS_VECTOR};
+use crate::acpi::{
+    AcpiError,
+    madt::{Madt, Polarity, TriggerMode},
+};
+use crate::memory::mmio::MmioError;
+
+pub mod io;
+pub mod local;
+
+use io::IoApic;
+use local::LocalApic;
+
+/// The ISA IRQs that get routed when switching to the APICs.
+const LEGACY_IRQS: &[InterruptIndex] = &[
+    InterruptIndex::Timer,
+    InterruptIndex::Keyboard,
+    InterruptIndex::Serial,
+    InterruptIndex::Rtc,
+];
+
+struct Apic {
+    local: LocalApic,
+    io_apics: Vec<IoApic>,
+    madt: Madt,
+}
+
+static APIC: OnceCell<Apic> = OnceCell::uninit();
+/// Set once the PICs are masked and interrupts arrive through the APICs, so they need the local
+/// APIC's EOI instead of the PIC's.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum ApicError {
+    /// CPUID says there's no local APIC.
+    Unsupported,
+    Acpi(AcpiError),
+    NoIoApic,
+    Mmio(MmioError),
+}
+
+impl core::fmt::Display for ApicError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ApicError::Unsupported => write!(f, "CPU has no local APIC"),
+            ApicError::Acpi(err) => write!(f, "{err}"),
+            ApicError::NoIoApic => write!(f, "MADT lists no I/O APIC"),
+            ApicError::Mmio(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// Switches interrupt delivery from the PICs to the APICs: enables the local APIC, routes the
+/// `LEGACY_IRQS` through the I/O APICs (following the MADT's overrides) and masks the PICs.
+///
+/// If anything's missing the PICs stay in charge, which is logged and returned as the error.
+/// Must be called after `acpi::init` and after the heap is up.
+pub fn init() -> Result<(), ApicError> {
+    let result = interrupts::without_interrupts(|| {
+        let apic = match APIC.get() {
+            Some(apic) => apic,
+            None => {
+                let apic = enable()?;
+                APIC.init_once(|| apic);
+                APIC.get().unwrap()
+            }
+        };
+        let bsp = apic.local.id();
+        for &index in LEGACY_IRQS {
+            let (gsi, polarity, trigger) = apic.madt.isa_irq(index.irq());
+            if let Some(io_apic) = apic.io_apic_for(gsi) {
+                io_apic.route(gsi, index.as_u8(), bsp, polarity, trigger, false);
+            }
+        }
+        // lines drivers registered handlers on before the switch
+        for irq in super::irq::active_lines() {
+            let (gsi, polarity, trigger) = apic.madt.isa_irq(irq);
+            if let Some(io_apic) = apic.io_apic_for(gsi) {
+                io_apic.route(gsi, super::irq::vector(irq), bsp, polarity, trigger, false);
+            }
+        }
+
+        unsafe { PICS.lock().disable() };
+        ENABLED.store(true, Ordering::Release);
+        Ok(apic)
+    });
+
+    match result {
+        Ok(apic) => {
+            log::info!(
+                "APIC: local APIC {} ({}), {} I/O APIC(s), PICs masked",
+                apic.local.id(),
+                if apic.local.is_x2apic() {
+                    "x2APIC"
+                } else {
+                    "xAPIC"
+                },
+                apic.io_apics.len()
+            );
+            Ok(())
+        }
+        Err(err) => {
+            log::warn!("APIC: {err}, staying on the 8259 PICs");
+            Err(err)
+        }
+    }
+}
+
+fn enable() -> Result<Apic, ApicError> {
+    if !local::supported().0 {
+        return Err(ApicError::Unsupported);
+    }
+    let madt = Madt::get().map_err(ApicError::Acpi)?;
+    if madt.io_apics.is_empty() {
+        return Err(ApicError::NoIoApic);
+    }
+
+    let io_apics = madt
+        .io_apics
+        .iter()
+        .map(|info| unsafe { IoApic::new(info) })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ApicError::Mmio)?;
+    let local = unsafe { LocalApic::enable(madt.local_apic_address, SPURIOUS_VECTOR) }
+        .map_err(ApicError::Mmio)?;
+
+    let uid = madt
+        .processors
+        .iter()
+        .find(|processor| processor.apic_id == local.id())
+        .map(|processor| processor.processor_uid);
+    for nmi in &madt.local_apic_nmis {
+        if nmi.processor_uid.is_none() || nmi.processor_uid == uid {
+            local.set_lint_nmi(nmi.lint, nmi.polarity, nmi.trigger);
+        }
+    }
+
+    Ok(Apic {
+        local,
+        io_apics,
+        madt,
+    })
+}
+
+impl Apic {
+    fn io_apic_for(&self, gsi: u32) -> Option<&IoApic> {
+        self.io_apics
+            .iter()
+            .find(|io_apic| io_apic.gsis().contains(&gsi))
+    }
+}
+
+/// Whether interrupts are coming through the APICs.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// The local APIC, once `init` got it going.
+pub fn local_apic() -> Option<&'static LocalApic> {
+    APIC.get().map(|apic| &apic.local)
+}
+
+/// Where ISA `irq` ends up after the MADT's overrides: the global system interrupt, its
+/// polarity and trigger mode. `None` before `init`.
+pub fn isa_irq(irq: u8) -> Option<(u32, Polarity, TriggerMode)> {
+    APIC.get().map(|apic| apic.madt.isa_irq(irq))
+}
+
+/// Routes ISA `irq` to `vector` on the boot CPU.
+pub fn route_isa_irq(irq: u8, vector: u8) {
+    let apic = APIC.get().expect("APIC not initalized");
+    let (gsi, polarity, trigger) = apic.madt.isa_irq(irq);
+    let io_apic = apic.io_apic_for(gsi).expect("no I/O APIC handles this IRQ");
+    io_apic.route(gsi, vector, apic.local.id(), polarity, trigger, false);
+}
+
+/// Whether one of the I/O APICs has an input for `gsi`. `false` before `init`.
+pub fn has_gsi(gsi: u32) -> bool {
+    APIC.get()
+        .is_some_and(|apic| apic.io_apic_for(gsi).is_some())
+}
+
+/// Routes global system interrupt `gsi` to `vector` on the boot CPU, for devices wired past the
+/// ISA IRQs.
+pub fn route_gsi(gsi: u32, vector: u8, polarity: Polarity, trigger: TriggerMode) {
+    let apic = APIC.get().expect("APIC not initalized");
+    let io_apic = apic.io_apic_for(gsi).expect("no I/O APIC handles this GSI");
+    io_apic.route(gsi, vector, apic.local.id(), polarity, trigger, false);
+}
+
+/// Masks or unmasks ISA `irq` at its I/O APIC.
+pub fn set_isa_irq_masked(irq: u8, masked: bool) {
+    let apic = APIC.get().expect("APIC not initalized");
+    let (gsi, ..) = apic.madt.isa_irq(irq);
+    if let Some(io_apic) = apic.io_apic_for(gsi) {
+        io_apic.set_masked(gsi, masked);
+    }
+}
+
+pub(super) fn end_of_interrupt() {
+    if let Some(apic) = APIC.get() {
+        apic.local.end_of_interrupt();
+    }
+}
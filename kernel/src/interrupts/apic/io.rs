@@ -0,0 +1,123 @@
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::acpi::madt::{self, Polarity, TriggerMode};
+use crate::memory::mmio::{self, MmioError, MmioRegion};
+
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+const VERSION: u32 = 0x01;
+const REDIRECTION_TABLE: u32 = 0x10;
+
+const ACTIVE_LOW: u64 = 1 << 13;
+const LEVEL_TRIGGERED: u64 = 1 << 15;
+const MASKED: u64 = 1 << 16;
+
+/// One I/O APIC, turning global system interrupts `gsi_base..gsi_base + entries` into vectors.
+#[derive(Debug)]
+pub struct IoApic {
+    pub id: u8,
+    pub gsi_base: u32,
+    entries: u32,
+    /// Registers are reached by selecting them in IOREGSEL then going through IOWIN, which can't
+    /// be interleaved.
+    regs: Mutex<MmioRegion>,
+}
+
+impl IoApic {
+    /// Maps the I/O APIC described by `info` and masks all of its entries.
+    ///
+    /// This function is unsafe because the caller must make sure `info` comes from the MADT.
+    pub unsafe fn new(info: &madt::IoApic) -> Result<Self, MmioError> {
+        let regs = unsafe { mmio::map_mmio(info.address, 0x20)? };
+        let mut io_apic = IoApic {
+            id: info.id,
+            gsi_base: info.gsi_base,
+            entries: 0,
+            regs: Mutex::new(regs),
+        };
+        io_apic.entries = ((io_apic.read(VERSION) >> 16) & 0xff) + 1;
+        for gsi in io_apic.gsis() {
+            io_apic.set_masked(gsi, true);
+        }
+        Ok(io_apic)
+    }
+
+    /// The global system interrupts this I/O APIC handles.
+    pub fn gsis(&self) -> core::ops::Range<u32> {
+        self.gsi_base..self.gsi_base + self.entries
+    }
+
+    /// Sends `gsi` to `vector` on the local APIC `destination`. Starts out masked if `masked`.
+    pub fn route(
+        &self,
+        gsi: u32,
+        vector: u8,
+        destination: u32,
+        polarity: Polarity,
+        trigger: TriggerMode,
+        masked: bool,
+    ) {
+        // fixed delivery, physical destination mode
+        let mut entry = u64::from(vector) | u64::from(destination & 0xff) << 56;
+        if polarity == Polarity::ActiveLow {
+            entry |= ACTIVE_LOW;
+        }
+        if trigger == TriggerMode::Level {
+            entry |= LEVEL_TRIGGERED;
+        }
+        if masked {
+            entry |= MASKED;
+        }
+        self.write_entry(gsi, entry);
+    }
+
+    pub fn set_masked(&self, gsi: u32, masked: bool) {
+        let entry = self.read_entry(gsi);
+        let entry = if masked {
+            entry | MASKED
+        } else {
+            entry & !MASKED
+        };
+        self.write_entry(gsi, entry);
+    }
+
+    fn read_entry(&self, gsi: u32) -> u64 {
+        let register = self.entry_register(gsi);
+        u64::from(self.read(register)) | u64::from(self.read(register + 1)) << 32
+    }
+
+    fn write_entry(&self, gsi: u32, entry: u64) {
+        let register = self.entry_register(gsi);
+        // high half first, so the entry never points at a half updated destination unmasked
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+
+    fn entry_register(&self, gsi: u32) -> u32 {
+        assert!(
+            self.gsis().contains(&gsi),
+            "GSI {gsi} is not on I/O APIC {}",
+            self.id
+        );
+        REDIRECTION_TABLE + (gsi - self.gsi_base) * 2
+    }
+
+    // interrupts stay off while the lock is held, a handler touching the I/O APIC would deadlock
+    fn read(&self, register: u32) -> u32 {
+        interrupts::without_interrupts(|| {
+            let regs = self.regs.lock();
+            regs.write(IOREGSEL, register);
+            regs.read(IOWIN)
+        })
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        interrupts::without_interrupts(|| {
+            let regs = self.regs.lock();
+            regs.write(IOREGSEL, register);
+            regs.write(IOWIN, value);
+        })
+    }
+}
@@ -0,0 +1,122 @@
+use core::arch::x86_64::__cpuid;
+use x86_64::{PhysAddr, registers::model_specific::Msr};
+
+use crate::acpi::madt::{Polarity, TriggerMode};
+use crate::memory::mmio::{self, MmioError, MmioRegion};
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+/// x2APIC registers are MSRs starting here, one per 16 byte xAPIC register.
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+// register offsets in the xAPIC MMIO page
+pub const ID: u32 = 0x20;
+pub const VERSION: u32 = 0x30;
+pub const TASK_PRIORITY: u32 = 0x80;
+pub const EOI: u32 = 0xb0;
+pub const SPURIOUS_VECTOR: u32 = 0xf0;
+pub const ERROR_STATUS: u32 = 0x280;
+pub const LVT_TIMER: u32 = 0x320;
+pub const LVT_LINT0: u32 = 0x350;
+pub const LVT_LINT1: u32 = 0x360;
+pub const LVT_ERROR: u32 = 0x370;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const DELIVERY_NMI: u32 = 0b100 << 8;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_LEVEL: u32 = 1 << 15;
+
+/// Whether the CPU has a local APIC, and whether it can do x2APIC mode.
+pub fn supported() -> (bool, bool) {
+    let features = unsafe { __cpuid(1) };
+    (features.edx & (1 << 9) != 0, features.ecx & (1 << 21) != 0)
+}
+
+/// The calling CPU's local APIC, accessed through MMIO in xAPIC mode or MSRs in x2APIC mode.
+#[derive(Debug)]
+pub enum LocalApic {
+    XApic(MmioRegion),
+    X2Apic,
+}
+
+impl LocalApic {
+    /// Enables the local APIC, in x2APIC mode if the CPU supports it. Interrupts are let through
+    /// (task priority 0) and spurious interrupts go to `spurious_vector`, everything in the LVT
+    /// other than LINT0/1 starts out masked.
+    ///
+    /// This function is unsafe because the caller must make sure `base` is the local APIC's
+    /// address from the MADT and that the CPU has one.
+    pub unsafe fn enable(base: PhysAddr, spurious_vector: u8) -> Result<Self, MmioError> {
+        let mut base_msr = Msr::new(IA32_APIC_BASE);
+        unsafe { base_msr.write(base_msr.read() | APIC_BASE_ENABLE) };
+        let apic = if supported().1 {
+            // going straight from disabled to x2APIC is a #GP, it has to be xAPIC mode first
+            unsafe { base_msr.write(base_msr.read() | APIC_BASE_ENABLE | APIC_BASE_X2APIC) };
+            LocalApic::X2Apic
+        } else {
+            LocalApic::XApic(unsafe { mmio::map_mmio(base, 4096)? })
+        };
+
+        apic.write(TASK_PRIORITY, 0);
+        apic.write(LVT_TIMER, LVT_MASKED);
+        apic.write(LVT_ERROR, LVT_MASKED);
+        apic.write(
+            SPURIOUS_VECTOR,
+            SOFTWARE_ENABLE | u32::from(spurious_vector),
+        );
+        Ok(apic)
+    }
+
+    pub fn is_x2apic(&self) -> bool {
+        matches!(self, LocalApic::X2Apic)
+    }
+
+    pub fn id(&self) -> u32 {
+        match self {
+            LocalApic::XApic(_) => self.read(ID) >> 24,
+            LocalApic::X2Apic => self.read(ID),
+        }
+    }
+
+    /// Reads the register at xAPIC offset `register`.
+    pub fn read(&self, register: u32) -> u32 {
+        match self {
+            LocalApic::XApic(regs) => regs.read(register as usize),
+            LocalApic::X2Apic => unsafe { Msr::new(x2apic_msr(register)).read() as u32 },
+        }
+    }
+
+    /// Writes the register at xAPIC offset `register`.
+    pub fn write(&self, register: u32, value: u32) {
+        match self {
+            LocalApic::XApic(regs) => regs.write(register as usize, value),
+            LocalApic::X2Apic => unsafe {
+                Msr::new(x2apic_msr(register)).write(u64::from(value));
+            },
+        }
+    }
+
+    /// Signals the end of the interrupt currently being handled.
+    pub fn end_of_interrupt(&self) {
+        self.write(EOI, 0);
+    }
+
+    /// Makes LINT pin `lint` (0 or 1) deliver an NMI.
+    pub fn set_lint_nmi(&self, lint: u8, polarity: Polarity, trigger: TriggerMode) {
+        let mut entry = DELIVERY_NMI;
+        if polarity == Polarity::ActiveLow {
+            entry |= LVT_ACTIVE_LOW;
+        }
+        if trigger == TriggerMode::Level {
+            entry |= LVT_LEVEL;
+        }
+        let register = if lint == 0 { LVT_LINT0 } else { LVT_LINT1 };
+        self.write(register, entry);
+    }
+}
+
+fn x2apic_msr(register: u32) -> u32 {
+    X2APIC_MSR_BASE + (register >> 4)
+}
@@ -0,0 +1,498 @@
+//! Handlers for every CPU exception.
+//!
+//! Anything that can't be recovered from gets dumped to the log (vector, decoded error code,
+//! stack frame, control registers and the bytes at the faulting instruction) before panicking,
+//! so the original cause isn't lost in a double fault.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode, SelectorErrorCode,
+};
+
+use crate::{backtrace, gdt, memory};
+
+pub mod mca;
+
+pub const DIVIDE_ERROR: u8 = 0;
+pub const DEBUG: u8 = 1;
+pub const NON_MASKABLE_INTERRUPT: u8 = 2;
+pub const BREAKPOINT: u8 = 3;
+pub const OVERFLOW: u8 = 4;
+pub const BOUND_RANGE_EXCEEDED: u8 = 5;
+pub const INVALID_OPCODE: u8 = 6;
+pub const DEVICE_NOT_AVAILABLE: u8 = 7;
+pub const DOUBLE_FAULT: u8 = 8;
+pub const INVALID_TSS: u8 = 10;
+pub const SEGMENT_NOT_PRESENT: u8 = 11;
+pub const STACK_SEGMENT_FAULT: u8 = 12;
+pub const GENERAL_PROTECTION_FAULT: u8 = 13;
+pub const PAGE_FAULT: u8 = 14;
+pub const X87_FLOATING_POINT: u8 = 16;
+pub const ALIGNMENT_CHECK: u8 = 17;
+pub const MACHINE_CHECK: u8 = 18;
+pub const SIMD_FLOATING_POINT: u8 = 19;
+pub const VIRTUALIZATION: u8 = 20;
+pub const CONTROL_PROTECTION: u8 = 21;
+pub const HV_INJECTION: u8 = 28;
+pub const VMM_COMMUNICATION: u8 = 29;
+pub const SECURITY: u8 = 30;
+
+/// Name and mnemonic of each vector.
+const EXCEPTIONS: [(&str, &str); 32] = [
+    ("divide error", "DE"),
+    ("debug", "DB"),
+    ("non-maskable interrupt", "NMI"),
+    ("breakpoint", "BP"),
+    ("overflow", "OF"),
+    ("bound range exceeded", "BR"),
+    ("invalid opcode", "UD"),
+    ("device not available", "NM"),
+    ("double fault", "DF"),
+    ("coprocessor segment overrun", "CSO"),
+    ("invalid TSS", "TS"),
+    ("segment not present", "NP"),
+    ("stack-segment fault", "SS"),
+    ("general protection fault", "GP"),
+    ("page fault", "PF"),
+    ("reserved", "-"),
+    ("x87 floating-point exception", "MF"),
+    ("alignment check", "AC"),
+    ("machine check", "MC"),
+    ("SIMD floating-point exception", "XM"),
+    ("virtualization exception", "VE"),
+    ("control protection exception", "CP"),
+    ("reserved", "-"),
+    ("reserved", "-"),
+    ("reserved", "-"),
+    ("reserved", "-"),
+    ("reserved", "-"),
+    ("reserved", "-"),
+    ("hypervisor injection exception", "HV"),
+    ("VMM communication exception", "VC"),
+    ("security exception", "SX"),
+    ("reserved", "-"),
+];
+
+/// What exception `vector` is called, like "general protection fault".
+pub fn name(vector: u8) -> &'static str {
+    EXCEPTIONS
+        .get(usize::from(vector))
+        .map_or("interrupt", |e| e.0)
+}
+
+/// The short name, like "GP" for `#GP`.
+pub fn mnemonic(vector: u8) -> &'static str {
+    EXCEPTIONS.get(usize::from(vector)).map_or("-", |e| e.1)
+}
+
+/// An exception's error code, decoded according to the vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    None,
+    /// #TS, #NP, #SS and #GP point at the segment selector (or IDT entry) that caused them. Kept
+    /// as pushed, `selector` decodes it (which loses the difference between the two IDT values of
+    /// the table bits).
+    Selector(u64),
+    PageFault(PageFaultErrorCode),
+    Raw(u64),
+}
+
+impl ErrorCode {
+    pub fn new(vector: u8, error_code: u64) -> ErrorCode {
+        match vector {
+            INVALID_TSS | SEGMENT_NOT_PRESENT | STACK_SEGMENT_FAULT | GENERAL_PROTECTION_FAULT => {
+                ErrorCode::Selector(error_code)
+            }
+            PAGE_FAULT => ErrorCode::PageFault(PageFaultErrorCode::from_bits_truncate(error_code)),
+            _ => ErrorCode::Raw(error_code),
+        }
+    }
+
+    pub fn bits(&self) -> Option<u64> {
+        match self {
+            ErrorCode::None => None,
+            ErrorCode::PageFault(code) => Some(code.bits()),
+            ErrorCode::Selector(code) | ErrorCode::Raw(code) => Some(*code),
+        }
+    }
+
+    /// The decoded selector, for the vectors that push one.
+    pub fn selector(&self) -> Option<SelectorErrorCode> {
+        match self {
+            ErrorCode::Selector(code) => Some(SelectorErrorCode::new_truncate(*code)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::None => write!(f, "none"),
+            ErrorCode::Selector(0) => write!(f, "0 (not selector related)"),
+            ErrorCode::Selector(bits) => {
+                let code = SelectorErrorCode::new_truncate(*bits);
+                write!(
+                    f,
+                    "{bits:#x} (selector index {} in the {:?}{})",
+                    code.index(),
+                    code.descriptor_table(),
+                    if code.external() { ", external" } else { "" }
+                )
+            }
+            ErrorCode::PageFault(code) => write!(f, "{:#x} ({:?})", code.bits(), code),
+            ErrorCode::Raw(code) => write!(f, "{code:#x}"),
+        }
+    }
+}
+
+/// How many return addresses `Caught` keeps.
+pub const CAUGHT_FRAMES: usize = 16;
+
+/// An exception `catch` stepped over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caught {
+    pub vector: u8,
+    pub error_code: ErrorCode,
+    pub instruction_pointer: VirtAddr,
+    /// What `backtrace::interrupted` found above the faulting code, innermost first. All `None`
+    /// if the handler's frame wasn't on the chain.
+    pub return_addresses: [Option<VirtAddr>; CAUGHT_FRAMES],
+}
+
+struct Catcher {
+    vector: u8,
+    skip: u64,
+    caught: Option<Caught>,
+}
+
+static CATCHER: Mutex<Option<Catcher>> = Mutex::new(None);
+
+/// Runs `f`, and if it raises exception `vector` resumes `skip` bytes past the instruction that
+/// raised it (0 for traps, which already point past it) instead of treating it as fatal. For
+/// probing things that might fault, and for testing the handlers.
+///
+/// Only the first exception is caught. Double faults and machine checks can't be caught.
+///
+/// This function is unsafe because the caller must guarantee that resuming `skip` bytes past the
+/// faulting instruction lands on an instruction boundary in `f`, and that nothing after it relies
+/// on what the skipped instruction would have done (a register it loads, say).
+pub unsafe fn catch(vector: u8, skip: u64, f: impl FnOnce()) -> Option<Caught> {
+    *CATCHER.lock() = Some(Catcher {
+        vector,
+        skip,
+        caught: None,
+    });
+    f();
+    CATCHER.lock().take().and_then(|catcher| catcher.caught)
+}
+
+fn try_catch(vector: u8, frame: &mut InterruptStackFrame, error_code: ErrorCode) -> bool {
+    let Some(mut catcher) = CATCHER.try_lock() else {
+        return false;
+    };
+    match catcher.as_mut() {
+        Some(catcher) if catcher.vector == vector && catcher.caught.is_none() => {
+            let mut return_addresses = [None; CAUGHT_FRAMES];
+            if let Some(frames) = backtrace::interrupted(frame) {
+                for (slot, frame) in return_addresses.iter_mut().zip(frames) {
+                    *slot = Some(frame.return_address);
+                }
+            }
+            catcher.caught = Some(Caught {
+                vector,
+                error_code,
+                instruction_pointer: frame.instruction_pointer,
+                return_addresses,
+            });
+            let skip = catcher.skip;
+            unsafe {
+                frame
+                    .as_mut()
+                    .update(|frame| frame.instruction_pointer += skip)
+            };
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Logs everything there is to know about an exception.
+pub fn dump(vector: u8, frame: &InterruptStackFrame, error_code: ErrorCode) {
+    log::error!(
+        "!!!!\nEXCEPTION: {} (#{}, vector {})",
+        name(vector),
+        mnemonic(vector),
+        vector
+    );
+    if error_code != ErrorCode::None {
+        log::error!("Error Code: {error_code}");
+    }
+    log::error!("{frame:#?}");
+    log::error!(
+        "CR0: {:?}\nCR2: {:?}\nCR3: {:?}\nCR4: {:?}",
+        Cr0::read(),
+        Cr2::read(),
+        Cr3::read(),
+        Cr4::read()
+    );
+    log::error!(
+        "Instruction Bytes: {}",
+        InstructionBytes(frame.instruction_pointer)
+    );
+    backtrace::log_interrupted(frame);
+}
+
+fn fatal(vector: u8, frame: &InterruptStackFrame, error_code: ErrorCode) -> ! {
+    dump(vector, frame, error_code);
+    panic!("EXCEPTION: {} (#{})", name(vector), mnemonic(vector));
+}
+
+/// Takes the frame by reference so a caught exception's new RIP lands in the frame `iretq` uses.
+fn handle(vector: u8, frame: &mut InterruptStackFrame, error_code: ErrorCode) {
+    if !try_catch(vector, frame, error_code) {
+        fatal(vector, frame, error_code);
+    }
+}
+
+/// Hex dump of the 16 bytes at an address, `??` where nothing's mapped.
+struct InstructionBytes(VirtAddr);
+
+impl fmt::Display for InstructionBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut addr = self.0;
+        for _ in 0..16 {
+            match memory::try_is_mapped(addr) {
+                Some(true) => write!(f, "{:02x} ", unsafe { addr.as_ptr::<u8>().read_volatile() })?,
+                Some(false) => write!(f, "?? ")?,
+                // page tables locked (maybe by whatever faulted) or memory isn't up yet
+                None => return write!(f, "(can't check the mapping)"),
+            }
+            addr += 1u64;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! handler {
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(mut frame: InterruptStackFrame) {
+            handle($vector, &mut frame, ErrorCode::None);
+        }
+    };
+    ($name:ident, $vector:expr, error_code) => {
+        extern "x86-interrupt" fn $name(mut frame: InterruptStackFrame, error_code: u64) {
+            handle($vector, &mut frame, ErrorCode::new($vector, error_code));
+        }
+    };
+}
+
+handler!(divide_error_handler, DIVIDE_ERROR);
+handler!(overflow_handler, OVERFLOW);
+handler!(bound_range_exceeded_handler, BOUND_RANGE_EXCEEDED);
+handler!(invalid_opcode_handler, INVALID_OPCODE);
+handler!(device_not_available_handler, DEVICE_NOT_AVAILABLE);
+handler!(invalid_tss_handler, INVALID_TSS, error_code);
+handler!(segment_not_present_handler, SEGMENT_NOT_PRESENT, error_code);
+handler!(stack_segment_fault_handler, STACK_SEGMENT_FAULT, error_code);
+handler!(
+    general_protection_fault_handler,
+    GENERAL_PROTECTION_FAULT,
+    error_code
+);
+handler!(x87_floating_point_handler, X87_FLOATING_POINT);
+handler!(alignment_check_handler, ALIGNMENT_CHECK, error_code);
+handler!(simd_floating_point_handler, SIMD_FLOATING_POINT);
+handler!(virtualization_handler, VIRTUALIZATION);
+handler!(control_protection_handler, CONTROL_PROTECTION, error_code);
+handler!(hv_injection_handler, HV_INJECTION);
+handler!(vmm_communication_handler, VMM_COMMUNICATION, error_code);
+handler!(security_handler, SECURITY, error_code);
+
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    unsafe {
+        idt.non_maskable_interrupt
+            .set_handler_fn(non_maskable_interrupt_handler)
+            .set_stack_index(gdt::NMI_IST_INDEX);
+    }
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded
+        .set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available
+        .set_handler_fn(device_not_available_handler);
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present
+        .set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault
+        .set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler);
+    unsafe {
+        idt.page_fault
+            .set_handler_fn(page_fault_handler)
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+    }
+    idt.x87_floating_point
+        .set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    unsafe {
+        idt.machine_check
+            .set_handler_fn(machine_check_handler)
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+    }
+    idt.simd_floating_point
+        .set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.cp_protection_exception
+        .set_handler_fn(control_protection_handler);
+    idt.hv_injection_exception
+        .set_handler_fn(hv_injection_handler);
+    idt.vmm_communication_exception
+        .set_handler_fn(vmm_communication_handler);
+    idt.security_exception.set_handler_fn(security_handler);
+}
+
+extern "x86-interrupt" fn debug_handler(mut frame: InterruptStackFrame) {
+    if !try_catch(DEBUG, &mut frame, ErrorCode::None) {
+        log::debug!("EXCEPTION: DEBUG\n{frame:#?}");
+    }
+}
+
+static NMI_COUNT: AtomicU64 = AtomicU64::new(0);
+/// The handler can't log, an NMI can land while the logger's lock is held and then nothing would
+/// ever release it. So it leaves what it saw here for `report_nmis`.
+static NMI_UNREPORTED: AtomicU64 = AtomicU64::new(0);
+static NMI_LAST_RIP: AtomicU64 = AtomicU64::new(0);
+static NMI_LAST_PORT_B: AtomicU8 = AtomicU8::new(0);
+
+/// NMIs seen so far, caught ones included.
+pub fn nmi_count() -> u64 {
+    NMI_COUNT.load(Ordering::Relaxed)
+}
+
+/// Logs the NMIs that came in since the last call. Call from normal context, the executor does
+/// whenever it runs out of work.
+pub fn report_nmis() {
+    let count = NMI_UNREPORTED.swap(0, Ordering::Acquire);
+    if count == 0 {
+        return;
+    }
+    // on a PC, system control port B says which of the chipset's NMI sources fired
+    let port_b = NMI_LAST_PORT_B.load(Ordering::Relaxed);
+    let reason = if port_b & 0x80 != 0 {
+        "memory parity error or PCI SERR"
+    } else if port_b & 0x40 != 0 {
+        "I/O channel check"
+    } else {
+        "unknown source"
+    };
+    log::warn!(
+        "{count} NON-MASKABLE INTERRUPT(S), the last from {reason} at {:#x}",
+        NMI_LAST_RIP.load(Ordering::Relaxed)
+    );
+}
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler(mut frame: InterruptStackFrame) {
+    NMI_COUNT.fetch_add(1, Ordering::Relaxed);
+    if try_catch(NON_MASKABLE_INTERRUPT, &mut frame, ErrorCode::None) {
+        return;
+    }
+
+    let port_b: u8 = unsafe { Port::new(0x61).read() };
+    NMI_LAST_PORT_B.store(port_b, Ordering::Relaxed);
+    NMI_LAST_RIP.store(frame.instruction_pointer.as_u64(), Ordering::Relaxed);
+    NMI_UNREPORTED.fetch_add(1, Ordering::Release);
+}
+
+extern "x86-interrupt" fn breakpoint_handler(mut frame: InterruptStackFrame) {
+    if !try_catch(BREAKPOINT, &mut frame, ErrorCode::None) {
+        log::debug!("EXCEPTION: BREAKPOINT\n{frame:#?}");
+    }
+}
+
+/// Page faults being handled. Every #PF starts at the top of the same IST stack, so one inside the
+/// handler (while mapping a VMA page, logging or dumping instruction bytes) has already overwritten
+/// the outer handler's frame, and there's nothing left to go back to.
+static PAGE_FAULT_DEPTH: AtomicU8 = AtomicU8::new(0);
+
+extern "x86-interrupt" fn page_fault_handler(
+    mut frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    if PAGE_FAULT_DEPTH.fetch_add(1, Ordering::Acquire) > 0 {
+        log::error!("page fault inside the page fault handler, its stack is clobbered");
+        fatal(PAGE_FAULT, &frame, ErrorCode::PageFault(error_code));
+    }
+    handle_page_fault(&mut frame, error_code);
+    PAGE_FAULT_DEPTH.fetch_sub(1, Ordering::Release);
+}
+
+fn handle_page_fault(frame: &mut InterruptStackFrame, error_code: PageFaultErrorCode) {
+    // faults inside a reserved memory area just get the page mapped, and the access retried
+    let reason = match Cr2::read() {
+        Ok(addr) => match memory::vma::handle_page_fault(addr, error_code) {
+            Ok(()) => return,
+            Err(err) => err,
+        },
+        Err(_) => memory::vma::FaultError::NoArea,
+    };
+    if try_catch(PAGE_FAULT, frame, ErrorCode::PageFault(error_code)) {
+        return;
+    }
+
+    log_stack_overflow();
+    log::error!("Reason: {reason}");
+    fatal(PAGE_FAULT, frame, ErrorCode::PageFault(error_code));
+}
+
+/// The stack whose guard page the last fatal fault hit.
+static OVERFLOWED_STACK: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// Name of the stack the last fatal page or double fault overflowed, if it was an overflow.
+/// Mostly for tests, which only get to look after the handler panicked.
+pub fn overflowed_stack() -> Option<&'static str> {
+    *OVERFLOWED_STACK.lock()
+}
+
+fn log_stack_overflow() {
+    if let Some(stack) = Cr2::read().ok().and_then(memory::stack::overflowed_stack) {
+        log::error!("stack overflow in stack {}", stack.name);
+        *OVERFLOWED_STACK.lock() = Some(stack.name);
+    }
+}
+
+extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, _error_code: u64) -> ! {
+    // a fault pushing onto a full stack faults again, CR2 still points at the guard page then
+    log_stack_overflow();
+    // the error code is always 0
+    fatal(DOUBLE_FAULT, &frame, ErrorCode::None);
+}
+
+extern "x86-interrupt" fn machine_check_handler(frame: InterruptStackFrame) -> ! {
+    // CR4.MCE only gets set when the banks exist, so they do if we're here
+    let status = mca::GlobalStatus::read();
+    log::error!(
+        "MCG_STATUS: {:#x} (restart ip valid: {}, error ip valid: {})",
+        status.0,
+        status.restart_ip_valid(),
+        status.error_ip_valid()
+    );
+    for error in mca::errors() {
+        log::error!("{error}");
+    }
+    fatal(MACHINE_CHECK, &frame, ErrorCode::None);
+}
@@ -0,0 +1,269 @@
+//! Machine check architecture: the banks of MSRs the CPU logs hardware errors (bad memory, cache
+//! parity, bus timeouts...) in before raising #MC.
+//!
+//! See the Intel SDM volume 3, chapter 16, "Machine-Check Architecture".
+
+use core::arch::x86_64::__cpuid;
+use core::fmt;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17a;
+const IA32_MCG_CTL: u32 = 0x17b;
+/// Each bank has CTL, STATUS, ADDR and MISC, in that order, starting here.
+const IA32_MC0_CTL: u32 = 0x400;
+
+const MCG_CTL_P: u64 = 1 << 8;
+
+/// Whether the CPU has machine checks and the bank MSRs.
+pub fn is_supported() -> bool {
+    const MCE: u32 = 1 << 7;
+    const MCA: u32 = 1 << 14;
+    let edx = unsafe { __cpuid(1) }.edx;
+    edx & (MCE | MCA) == MCE | MCA
+}
+
+/// How many error reporting banks there are.
+pub fn bank_count() -> u8 {
+    if !is_supported() {
+        return 0;
+    }
+    unsafe { Msr::new(IA32_MCG_CAP).read() as u8 }
+}
+
+/// Turns on error reporting in every bank and sets CR4.MCE, so a machine check raises #MC instead
+/// of shutting the machine down. Errors left over from before boot are logged and cleared.
+pub fn init() {
+    if !is_supported() {
+        log::warn!("no machine check architecture, hardware errors will reset the machine");
+        return;
+    }
+
+    for error in errors() {
+        log::warn!("machine check from before boot: {error}");
+    }
+    unsafe {
+        if Msr::new(IA32_MCG_CAP).read() & MCG_CTL_P != 0 {
+            Msr::new(IA32_MCG_CTL).write(u64::MAX);
+        }
+        for bank in 0..bank_count() {
+            Msr::new(bank_msr(bank, 0)).write(u64::MAX);
+            Msr::new(bank_msr(bank, 1)).write(0);
+        }
+        Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION));
+    }
+}
+
+fn bank_msr(bank: u8, register: u32) -> u32 {
+    IA32_MC0_CTL + 4 * u32::from(bank) + register
+}
+
+/// IA32_MCG_STATUS, what state the machine check left the CPU in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlobalStatus(pub u64);
+
+impl GlobalStatus {
+    pub fn read() -> GlobalStatus {
+        GlobalStatus(unsafe { Msr::new(IA32_MCG_STATUS).read() })
+    }
+
+    /// The pushed RIP can be returned to.
+    pub fn restart_ip_valid(&self) -> bool {
+        self.0 & (1 << 0) != 0
+    }
+
+    /// The pushed RIP is the instruction the error happened at.
+    pub fn error_ip_valid(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    /// A machine check is being handled. Another one while this is set shuts the CPU down.
+    pub fn in_progress(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+}
+
+/// A bank with a valid error logged in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankError {
+    pub bank: u8,
+    /// IA32_MCi_STATUS
+    pub status: u64,
+    /// IA32_MCi_ADDR, if the status says it's valid.
+    pub address: Option<u64>,
+    /// IA32_MCi_MISC, if the status says it's valid.
+    pub misc: Option<u64>,
+}
+
+impl BankError {
+    pub const VALID: u64 = 1 << 63;
+    pub const OVERFLOW: u64 = 1 << 62;
+    pub const UNCORRECTED: u64 = 1 << 61;
+    pub const ENABLED: u64 = 1 << 60;
+    pub const MISC_VALID: u64 = 1 << 59;
+    pub const ADDRESS_VALID: u64 = 1 << 58;
+    /// Processor context corrupt, there's nothing to go back to.
+    pub const CONTEXT_CORRUPT: u64 = 1 << 57;
+
+    /// Reads bank `bank`, `None` if it hasn't logged anything.
+    pub fn read(bank: u8) -> Option<BankError> {
+        let status = unsafe { Msr::new(bank_msr(bank, 1)).read() };
+        if status & Self::VALID == 0 {
+            return None;
+        }
+        let read_if = |flag, register| {
+            (status & flag != 0).then(|| unsafe { Msr::new(bank_msr(bank, register)).read() })
+        };
+        Some(BankError {
+            bank,
+            status,
+            address: read_if(Self::ADDRESS_VALID, 2),
+            misc: read_if(Self::MISC_VALID, 3),
+        })
+    }
+
+    pub fn code(&self) -> McaErrorCode {
+        McaErrorCode(self.status as u16)
+    }
+
+    pub fn model_specific_code(&self) -> u16 {
+        (self.status >> 16) as u16
+    }
+
+    pub fn is_uncorrected(&self) -> bool {
+        self.status & Self::UNCORRECTED != 0
+    }
+
+    pub fn context_corrupt(&self) -> bool {
+        self.status & Self::CONTEXT_CORRUPT != 0
+    }
+
+    /// Marks the bank as read so it can log the next error.
+    pub fn clear(&self) {
+        unsafe { Msr::new(bank_msr(self.bank, 1)).write(0) };
+    }
+}
+
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bank {}: {} {} (status {:#018x}, model specific {:#06x})",
+            self.bank,
+            if self.is_uncorrected() {
+                "uncorrected"
+            } else {
+                "corrected"
+            },
+            self.code(),
+            self.status,
+            self.model_specific_code()
+        )?;
+        if self.status & Self::OVERFLOW != 0 {
+            write!(f, ", earlier errors lost")?;
+        }
+        if self.context_corrupt() {
+            write!(f, ", context corrupt")?;
+        }
+        if let Some(address) = self.address {
+            write!(f, ", address {address:#x}")?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, ", misc {misc:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Every bank with an error logged in it.
+pub fn errors() -> impl Iterator<Item = BankError> {
+    (0..bank_count()).filter_map(BankError::read)
+}
+
+/// The architectural low 16 bits of IA32_MCi_STATUS, which say what kind of error it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McaErrorCode(pub u16);
+
+impl fmt::Display for McaErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const LEVELS: [&str; 4] = ["L0", "L1", "L2", "generic level"];
+        const TRANSACTIONS: [&str; 4] = ["instruction", "data", "generic", "reserved"];
+        const REQUESTS: [&str; 9] = [
+            "generic",
+            "read",
+            "write",
+            "data read",
+            "data write",
+            "instruction fetch",
+            "prefetch",
+            "eviction",
+            "snoop",
+        ];
+        const MEMORY_REQUESTS: [&str; 5] =
+            ["generic", "read", "write", "address/command", "scrubbing"];
+        const PARTICIPATION: [&str; 4] = [
+            "local processor originated",
+            "local processor responded",
+            "local processor observed",
+            "generic",
+        ];
+
+        let code = self.0;
+        let level = LEVELS[usize::from(code & 0b11)];
+        let transaction = TRANSACTIONS[usize::from((code >> 2) & 0b11)];
+        let request = |r: u16| REQUESTS.get(usize::from(r)).copied().unwrap_or("reserved");
+        // corrected errors the hardware didn't signal, they're only in the bank
+        let filtered = if code & (1 << 12) != 0 {
+            ", filtered"
+        } else {
+            ""
+        };
+
+        match code {
+            0x0000 => write!(f, "no error"),
+            0x0001 => write!(f, "unclassified error"),
+            0x0002 => write!(f, "microcode ROM parity error"),
+            0x0003 => write!(f, "external error"),
+            0x0004 => write!(f, "functional redundancy check error"),
+            0x0005 => write!(f, "internal parity error"),
+            0x0006 => write!(f, "SMM handler code access violation"),
+            0x0400 => write!(f, "internal timer error"),
+            0x0e0b => write!(f, "I/O error"),
+            _ if code & 0xfc00 == 0x0400 => write!(f, "internal unclassified error"),
+            _ if code & 0xeffc == 0x000c => write!(f, "{level} cache hierarchy error{filtered}"),
+            _ if code & 0xeff0 == 0x0010 => {
+                write!(f, "{level} {transaction} TLB error{filtered}")
+            }
+            _ if code & 0xef80 == 0x0080 => {
+                let memory_request = MEMORY_REQUESTS
+                    .get(usize::from((code >> 4) & 0b111))
+                    .copied()
+                    .unwrap_or("reserved");
+                write!(f, "memory controller {memory_request} error")?;
+                match code & 0xf {
+                    0xf => {}
+                    channel => write!(f, " on channel {channel}")?,
+                }
+                write!(f, "{filtered}")
+            }
+            _ if code & 0xef00 == 0x0100 => write!(
+                f,
+                "{level} {transaction} cache {} error{filtered}",
+                request((code >> 4) & 0xf)
+            ),
+            _ if code & 0xe800 == 0x0800 => write!(
+                f,
+                "bus error, {}, {} request{}{filtered}",
+                PARTICIPATION[usize::from((code >> 9) & 0b11)],
+                request((code >> 4) & 0xf),
+                if code & (1 << 8) != 0 {
+                    ", timed out"
+                } else {
+                    ""
+                }
+            ),
+            _ => write!(f, "unknown error {code:#06x}"),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Handlers that drivers register for the ISA IRQ lines at runtime.
+//!
+//! Every line's vector goes through the same entry point, which runs the kernel's own handler
+//! for the line (if it has one), then everything registered on it, then sends the EOI. Lines can
+//! be shared, each handler says whether the interrupt was its device's.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::RwLock;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+use super::{PIC_1_OFFSET, PICS, apic};
+
+/// ISA IRQs 0 to 15, on the two PICs or the first 16 inputs of the I/O APIC.
+pub const IRQ_LINES: u8 = 16;
+/// Chains the slave PIC into the master, so nothing can use it.
+const CASCADE: u8 = 2;
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xa0;
+/// OCW3, makes the next read of the command port return the in-service register.
+const READ_ISR: u8 = 0x0b;
+const NON_SPECIFIC_EOI: u8 = 0x20;
+
+/// What a handler says about an interrupt on a shared line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// Its device raised it, and it was dealt with.
+    Handled,
+    /// Someone else's.
+    NotMine,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// Not an ISA IRQ.
+    InvalidIrq(u8),
+    /// IRQ 2 is the PIC cascade.
+    Cascade,
+}
+
+impl fmt::Display for IrqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrqError::InvalidIrq(irq) => write!(f, "IRQ {irq} doesn't exist"),
+            IrqError::Cascade => write!(f, "IRQ 2 is the PIC cascade"),
+        }
+    }
+}
+
+type Handler = Box<dyn Fn() -> IrqReturn + Send + Sync>;
+
+struct Registration {
+    id: u64,
+    handler: Handler,
+}
+
+/// Only written with interrupts off, so the interrupt path never finds a line locked.
+static HANDLERS: [RwLock<Vec<Registration>>; IRQ_LINES as usize] =
+    [const { RwLock::new(Vec::new()) }; IRQ_LINES as usize];
+static COUNTS: [AtomicU64; IRQ_LINES as usize] = [const { AtomicU64::new(0) }; IRQ_LINES as usize];
+static UNHANDLED: [AtomicU64; IRQ_LINES as usize] =
+    [const { AtomicU64::new(0) }; IRQ_LINES as usize];
+static SPURIOUS: [AtomicU64; IRQ_LINES as usize] =
+    [const { AtomicU64::new(0) }; IRQ_LINES as usize];
+
+/// Unregisters its handler when dropped.
+#[derive(Debug)]
+#[must_use = "the handler is unregistered as soon as the handle is dropped"]
+pub struct IrqHandle {
+    irq: u8,
+    id: u64,
+}
+
+impl IrqHandle {
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+}
+
+impl Drop for IrqHandle {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| {
+            let mut handlers = HANDLERS[usize::from(self.irq)].write();
+            handlers.retain(|registration| registration.id != self.id);
+            if handlers.is_empty() && super::builtin_handler(self.irq).is_none() {
+                set_masked(self.irq, true);
+            }
+        });
+    }
+}
+
+/// Adds `handler` to IRQ line `irq` and unmasks the line. It runs in interrupt context, so it
+/// must not block or allocate, and the EOI is sent for it afterwards.
+pub fn register_irq(
+    irq: u8,
+    handler: impl Fn() -> IrqReturn + Send + Sync + 'static,
+) -> Result<IrqHandle, IrqError> {
+    if irq >= IRQ_LINES {
+        return Err(IrqError::InvalidIrq(irq));
+    }
+    if irq == CASCADE {
+        return Err(IrqError::Cascade);
+    }
+
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let registration = Registration {
+        id,
+        handler: Box::new(handler),
+    };
+    interrupts::without_interrupts(|| {
+        HANDLERS[usize::from(irq)].write().push(registration);
+        set_masked(irq, false);
+    });
+    Ok(IrqHandle { irq, id })
+}
+
+/// The vector IRQ `irq` arrives on.
+pub fn vector(irq: u8) -> u8 {
+    PIC_1_OFFSET + irq
+}
+
+/// Interrupts seen on `irq` so far.
+pub fn count(irq: u8) -> u64 {
+    COUNTS[usize::from(irq)].load(Ordering::Relaxed)
+}
+
+/// Interrupts on `irq` that no handler claimed.
+pub fn unhandled(irq: u8) -> u64 {
+    UNHANDLED[usize::from(irq)].load(Ordering::Relaxed)
+}
+
+/// Spurious PIC interrupts on `irq` so far. Only IRQ 7 and 15 get them, they don't reach the
+/// handlers or show up in `count`.
+pub fn spurious(irq: u8) -> u64 {
+    SPURIOUS[usize::from(irq)].load(Ordering::Relaxed)
+}
+
+/// Whether anything is registered on `irq`, not counting the kernel's own handler.
+pub fn has_handlers(irq: u8) -> bool {
+    HANDLERS[usize::from(irq)]
+        .try_read()
+        .is_some_and(|handlers| !handlers.is_empty())
+}
+
+/// Lines with registered handlers, which `apic::init` has to route too.
+pub(super) fn active_lines() -> impl Iterator<Item = u8> {
+    (0..IRQ_LINES).filter(|&irq| has_handlers(irq))
+}
+
+/// Masks or unmasks `irq` at whichever controller is delivering interrupts.
+pub fn set_masked(irq: u8, masked: bool) {
+    if apic::is_enabled() {
+        if masked {
+            apic::set_isa_irq_masked(irq, true);
+        } else {
+            apic::route_isa_irq(irq, vector(irq));
+        }
+        return;
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        let [mut master, mut slave] = unsafe { pics.read_masks() };
+        let (mask, bit) = if irq < 8 {
+            (&mut master, irq)
+        } else {
+            (&mut slave, irq - 8)
+        };
+        if masked {
+            *mask |= 1 << bit;
+        } else {
+            *mask &= !(1 << bit);
+        }
+        if irq >= 8 && !masked {
+            master &= !(1 << CASCADE);
+        }
+        unsafe { pics.write_masks(master, slave) };
+    });
+}
+
+/// When a request goes away before the CPU acknowledges it, a PIC raises its lowest priority line
+/// (IRQ 7, or 15 on the slave) anyway, without setting the line's in-service bit.
+fn is_spurious(irq: u8) -> bool {
+    let command = match irq {
+        7 => PIC_1_COMMAND,
+        15 => PIC_2_COMMAND,
+        _ => return false,
+    };
+    if apic::is_enabled() {
+        return false;
+    }
+    let _pics = PICS.lock();
+    let in_service = unsafe {
+        Port::<u8>::new(command).write(READ_ISR);
+        Port::<u8>::new(command).read()
+    };
+    in_service & (1 << 7) == 0
+}
+
+fn dispatch(irq: u8) {
+    if is_spurious(irq) {
+        SPURIOUS[usize::from(irq)].fetch_add(1, Ordering::Relaxed);
+        // no EOI for the PIC that made it up, but the master did see a real IRQ 2 from the slave
+        if irq == 15 {
+            let _pics = PICS.lock();
+            unsafe { Port::<u8>::new(PIC_1_COMMAND).write(NON_SPECIFIC_EOI) };
+        }
+        return;
+    }
+    COUNTS[usize::from(irq)].fetch_add(1, Ordering::Relaxed);
+
+    let mut handled = false;
+    if let Some(handler) = super::builtin_handler(irq) {
+        handler();
+        handled = true;
+    }
+    if let Some(handlers) = HANDLERS[usize::from(irq)].try_read() {
+        for registration in handlers.iter() {
+            if (registration.handler)() == IrqReturn::Handled {
+                handled = true;
+            }
+        }
+    }
+    if !handled {
+        UNHANDLED[usize::from(irq)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    super::end_of_vector(vector(irq));
+}
+
+extern "x86-interrupt" fn irq_entry<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    dispatch(IRQ);
+}
+
+macro_rules! install_lines {
+    ($idt:ident, $($irq:literal)*) => {
+        $( $idt[vector($irq)].set_handler_fn(irq_entry::<$irq>); )*
+    };
+}
+
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    install_lines!(idt, 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+}
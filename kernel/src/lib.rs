@@ -5,6 +5,7 @@
 #![reexport_test_harness_main = "test_main"]
 #![feature(abi_x86_interrupt)]
 #![feature(type_alias_impl_trait)]
+#![feature(allocator_api)]
 
 use core::panic::PanicInfo;
 extern crate alloc;
@@ -15,12 +16,16 @@ use bootloader_api::{BootInfo, entry_point};
 use bootloader_x86_64_common::logger::LockedLogger;
 use conquer_once::spin::OnceCell;
 
+pub mod acpi;
 pub mod allocator;
+pub mod backtrace;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
+pub mod power;
 pub mod serial;
 pub mod task;
+pub mod time;
 
 pub static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
@@ -41,6 +46,7 @@ pub unsafe fn init_logger(buffer: &'static mut [u8], info: FrameBufferInfo) {
 pub fn init() {
     gdt::init();
     interrupts::init_idt();
+    interrupts::exceptions::mca::init();
     unsafe { interrupts::PICS.lock().initialize() }; // unsafe, if PIC is configured wrong may -> UB
     x86_64::instructions::interrupts::enable();
 }
@@ -109,6 +115,9 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("error: {}\n", info);
+    for (i, frame) in backtrace::trace().enumerate() {
+        serial_println!("  #{:<2} {}", i, frame);
+    }
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
@@ -8,7 +8,7 @@ use bootloader_api::{BootInfo, entry_point};
 use core::panic::PanicInfo;
 use kernel::{
     allocator,
-    memory::{self, BootInfoFrameAllocator},
+    memory,
     task::{Task, executor::Executor, keyboard},
 };
 use x86_64::VirtAddr;
@@ -16,7 +16,6 @@ use x86_64::VirtAddr;
 extern crate alloc;
 
 mod framebuffer;
-mod serial;
 
 entry_point!(kernel_main, config = &kernel::BOOTLOADER_CONFIG);
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
@@ -24,11 +23,31 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     let frame_buffer_struct = (&mut boot_info.framebuffer).as_mut().unwrap();
     let frame_buffer_info = frame_buffer_struct.info().clone();
     unsafe { kernel::init_logger(frame_buffer_struct.buffer_mut(), frame_buffer_info) };
+    let symbols = unsafe {
+        kernel::backtrace::symbols::init(
+            boot_info.ramdisk_addr.into_option(),
+            boot_info.ramdisk_len,
+            boot_info.kernel_image_offset,
+        )
+    };
+    match symbols {
+        Ok(count) => log::info!("loaded {count} kernel symbols for backtraces"),
+        Err(err) => log::warn!("no kernel symbols, backtraces will be bare addresses: {err}"),
+    }
     let phys_mem_offset =
         VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap init failed :(");
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    allocator::init_heap().expect("heap init failed :(");
+    memory::log_memory_map(&boot_info.memory_regions);
+    kernel::gdt::init_stacks();
+    match unsafe { kernel::acpi::init(boot_info.rsdp_addr.into_option()) } {
+        Ok(()) => {
+            kernel::acpi::log_summary();
+            _ = kernel::interrupts::apic::init();
+        }
+        Err(err) => log::warn!("ACPI: {err}, staying on the 8259 PICs"),
+    }
+    kernel::time::init();
 
     #[cfg(test)]
     test_main();
@@ -42,6 +61,7 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     log::error!("!!!KERNEL PANIC!!!\n{}", info); // those who panic
+    kernel::backtrace::log(kernel::backtrace::trace());
     kernel::hlt_loop();
 }
 
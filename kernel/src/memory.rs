@@ -1,24 +1,175 @@
-use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind, MemoryRegions};
+use buddy::BuddyFrameAllocator;
+use conquer_once::spin::OnceCell;
+use spin::{Mutex, MutexGuard};
+use stats::{MemoryMap, MemoryStats};
 use x86_64::{
     PhysAddr, VirtAddr,
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    registers::control::Cr3,
+    structures::paging::{
+        FrameAllocator, OffsetPageTable, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    },
 };
 
-/// Initalize a new OffsetPageTable
+use crate::allocator::{HEAP_MAX_SIZE, HEAP_START};
+
+pub mod address_space;
+pub mod bitmap;
+pub mod buddy;
+pub mod mmio;
+pub mod stack;
+pub mod stats;
+pub mod virt;
+pub mod vma;
+
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+static KERNEL_LEVEL_4_FRAME: OnceCell<PhysFrame> = OnceCell::uninit();
+static MAPPER: OnceCell<Mutex<OffsetPageTable<'static>>> = OnceCell::uninit();
+static FRAME_ALLOCATOR: OnceCell<Mutex<BuddyFrameAllocator>> = OnceCell::uninit();
+/// Total and usable bytes in the memory map, the map itself can't be kept in a static.
+static PHYSICAL_BYTES: OnceCell<(u64, u64)> = OnceCell::uninit();
+
+/// Where the kernel maps things after boot, as start and size. Kernel stacks never get anywhere
+/// near the end of their level 4 slot.
+const KERNEL_REGIONS: [(u64, u64); 3] = [
+    (HEAP_START as u64, HEAP_MAX_SIZE as u64),
+    (stack::STACK_REGION_START, 1 << 39),
+    (virt::VIRT_REGION_START, virt::VIRT_REGION_SIZE),
+];
+
+/// Initalize the kernel's OffsetPageTable and frame allocator
 ///
 /// This function is unsafe because the caller must make sure that all of physical memory
-/// is mapped to virtual memory at the offset passed in `physical_memory_offset`.
+/// is mapped to virtual memory at the offset passed in `physical_memory_offset`, and that all
+/// frames marked as `USABLE` in `memory_regions` are actually unused.
 /// In addition, this must only be called once to avoid aliasing `&mut` references.
-pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
-    unsafe {
+pub unsafe fn init(physical_memory_offset: VirtAddr, memory_regions: &'static MemoryRegions) {
+    let mut mapper = unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
         OffsetPageTable::new(level_4_table, physical_memory_offset)
+    };
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(memory_regions, physical_memory_offset) };
+    preallocate_kernel_slots(&mut mapper, &mut frame_allocator);
+
+    PHYSICAL_MEMORY_OFFSET.init_once(|| physical_memory_offset);
+    KERNEL_LEVEL_4_FRAME.init_once(|| Cr3::read().0);
+    MAPPER.init_once(|| Mutex::new(mapper));
+    FRAME_ALLOCATOR.init_once(|| Mutex::new(frame_allocator));
+    PHYSICAL_BYTES.init_once(|| {
+        let bytes = |region: &MemoryRegion| region.end - region.start;
+        let usable = memory_regions
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::Usable);
+        (
+            memory_regions.iter().map(bytes).sum(),
+            usable.map(bytes).sum(),
+        )
+    });
+
+    unsafe { mmio::init_pat() };
+}
+
+/// Gives every level 4 slot in `KERNEL_REGIONS` a level 3 table now, so address spaces (which
+/// share the kernel's level 4 entries as they are when created) see whatever the kernel maps there
+/// later, like heap growth and MMIO.
+fn preallocate_kernel_slots(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BuddyFrameAllocator,
+) {
+    let offset = mapper.phys_offset();
+    for (start, size) in KERNEL_REGIONS {
+        let first = usize::from(VirtAddr::new(start).p4_index());
+        let last = usize::from(VirtAddr::new(start + size - 1).p4_index());
+        for i in first..=last {
+            let entry = &mut mapper.level_4_table_mut()[i];
+            if !entry.is_unused() {
+                continue;
+            }
+            let frame: PhysFrame = frame_allocator
+                .allocate_frame()
+                .expect("no frame for a kernel level 3 table");
+            let table: *mut PageTable = (offset + frame.start_address().as_u64()).as_mut_ptr();
+            unsafe { (*table).zero() };
+            entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
     }
 }
 
-unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
-    use x86_64::registers::control::Cr3;
+/// The virtual address all of physical memory is mapped at.
+pub fn physical_memory_offset() -> VirtAddr {
+    *PHYSICAL_MEMORY_OFFSET.get().expect("memory not initalized")
+}
+
+/// The frame of the level 4 table the kernel booted with.
+pub fn kernel_level_4_frame() -> PhysFrame {
+    *KERNEL_LEVEL_4_FRAME.get().expect("memory not initalized")
+}
 
+/// Locks the kernel's page table mapper.
+pub fn mapper() -> MutexGuard<'static, OffsetPageTable<'static>> {
+    MAPPER.get().expect("memory not initalized").lock()
+}
+
+/// Locks the kernel's frame allocator.
+pub fn frame_allocator() -> MutexGuard<'static, BuddyFrameAllocator> {
+    FRAME_ALLOCATOR.get().expect("memory not initalized").lock()
+}
+
+/// Physical memory and heap usage right now.
+pub fn stats() -> MemoryStats {
+    let (total_bytes, usable_bytes) = *PHYSICAL_BYTES.get().expect("memory not initalized");
+    let (total_frames, allocated_frames, free_frames) = {
+        let frame_allocator = frame_allocator();
+        (
+            frame_allocator.total_frames(),
+            frame_allocator.used_frames(),
+            frame_allocator.free_frames(),
+        )
+    };
+    let heap = crate::allocator::stats();
+
+    MemoryStats {
+        total_bytes,
+        usable_bytes,
+        total_frames,
+        allocated_frames,
+        free_frames,
+        heap_size: heap.heap_size,
+        heap_used: heap.bytes_in_use,
+        heap_free: heap.heap_free,
+    }
+}
+
+/// Logs the bootloader's memory map and the current usage. Needs the heap to be initalized.
+pub fn log_memory_map(memory_regions: &MemoryRegions) {
+    log::info!("memory map:\n{}", MemoryMap(memory_regions));
+    log::info!("memory usage:\n{}", stats());
+}
+
+/// Whether `addr` is mapped, without waiting for the page table lock. `None` if memory isn't up
+/// yet or the lock is held, which it may well be when an exception handler asks.
+pub(crate) fn try_is_mapped(addr: VirtAddr) -> Option<bool> {
+    use x86_64::structures::paging::Translate;
+    let mapper = MAPPER.get()?.try_lock()?;
+    Some(mapper.translate_addr(addr).is_some())
+}
+
+/// Locks both the mapper and the frame allocator, or returns `None` if either is uninitalized or
+/// already held.
+///
+/// Meant for paths that can run while the locks are held further up the stack (the heap growing
+/// from inside an allocation), where spinning would deadlock.
+pub(crate) fn try_lock() -> Option<(
+    MutexGuard<'static, OffsetPageTable<'static>>,
+    MutexGuard<'static, BuddyFrameAllocator>,
+)> {
+    let mapper = MAPPER.get()?.try_lock()?;
+    let frame_allocator = FRAME_ALLOCATOR.get()?.try_lock()?;
+    Some((mapper, frame_allocator))
+}
+
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
     let (level_4_table_frame, _) = Cr3::read();
 
     let phys = level_4_table_frame.start_address();
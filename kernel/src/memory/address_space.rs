@@ -0,0 +1,223 @@
+use x86_64::{
+    PhysAddr, VirtAddr,
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB, Translate,
+        mapper::{MapToError, MapperFlush, TranslateResult},
+    },
+};
+
+use super::{frame_allocator, kernel_level_4_frame, physical_memory_offset};
+
+/// Marks leaf entries whose frame was allocated by the address space itself, so it knows which
+/// frames to give back on unmap and drop. Bit 9 is one of the bits left free for the OS.
+const OWNED: PageTableFlags = PageTableFlags::BIT_9;
+
+/// A set of page tables that can be switched to with `switch`.
+///
+/// A new address space starts out sharing every level 4 entry the kernel is using at that point
+/// (the bootloader's dynamic mappings don't stay in the higher half, so the kernel's code, stack,
+/// heap and physical memory mapping can sit in any slot). `memory::init` fills in the slots of the
+/// regions the kernel maps into later up front, so those mappings show up here too. All other
+/// slots are private to the address space, that's where `map_range` and `unmap_range` operate.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    shared: [bool; 512],
+}
+
+impl AddressSpace {
+    /// Allocates a fresh level 4 table that shares the kernel's entries.
+    pub fn new() -> Result<Self, MapToError<Size4KiB>> {
+        let level_4_frame: PhysFrame = frame_allocator()
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        let kernel_table = unsafe { &*table_ptr(kernel_level_4_frame()) };
+        let table = unsafe { &mut *table_ptr(level_4_frame) };
+        table.zero();
+
+        let mut shared = [false; 512];
+        for (i, entry) in kernel_table.iter().enumerate() {
+            if !entry.is_unused() {
+                table[i] = entry.clone();
+                shared[i] = true;
+            }
+        }
+
+        Ok(AddressSpace {
+            level_4_frame,
+            shared,
+        })
+    }
+
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Whether this address space is the one loaded in CR3.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// Maps `size` bytes starting at `start` (both rounded out to whole pages) to freshly
+    /// allocated frames. The frames are freed again by `unmap_range` or when the address space is
+    /// dropped.
+    pub fn map_range(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let mut frame_allocator = frame_allocator();
+        for page in self.pages(start, size) {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            let result = unsafe {
+                self.mapper()
+                    .map_to(page, frame, flags | OWNED, &mut *frame_allocator)
+            };
+            match result {
+                Ok(flush) => self.flush(flush),
+                Err(err) => {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps `page` to a frame the caller owns, like a device or a buffer shared with the kernel.
+    /// The frame is not freed when unmapped.
+    ///
+    /// This function is unsafe because the caller must make sure the frame is not in use for
+    /// anything that mapping it could break.
+    pub unsafe fn map_to(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        self.check_private(page);
+        let flush = unsafe {
+            self.mapper()
+                .map_to(page, frame, flags - OWNED, &mut *frame_allocator())?
+        };
+        self.flush(flush);
+        Ok(())
+    }
+
+    /// Unmaps `size` bytes starting at `start`, skipping pages that are not mapped. Frames that
+    /// were allocated by `map_range` are freed.
+    pub fn unmap_range(&mut self, start: VirtAddr, size: u64) {
+        for page in self.pages(start, size) {
+            let owned = match self.mapper().translate(page.start_address()) {
+                TranslateResult::Mapped { flags, .. } => flags.contains(OWNED),
+                _ => continue,
+            };
+            let Ok((frame, flush)) = self.mapper().unmap(page) else {
+                continue;
+            };
+            self.flush(flush);
+            if owned {
+                unsafe { frame_allocator().deallocate_frame(frame) };
+            }
+        }
+    }
+
+    /// Translates a virtual address through this address space's page tables.
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        let table = unsafe { &mut *table_ptr(self.level_4_frame) };
+        let mapper = unsafe { OffsetPageTable::new(table, physical_memory_offset()) };
+        mapper.translate_addr(addr)
+    }
+
+    /// Loads this address space into CR3.
+    ///
+    /// This function is unsafe because the caller must make sure that everything the running
+    /// code touches (its stack, the heap, ...) is mapped here too. For kernel memory that holds
+    /// as long as it was mapped before the address space was created.
+    pub unsafe fn switch(&self) {
+        unsafe { Cr3::write(self.level_4_frame, Cr3Flags::empty()) };
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let table = unsafe { &mut *table_ptr(self.level_4_frame) };
+        unsafe { OffsetPageTable::new(table, physical_memory_offset()) }
+    }
+
+    fn pages(&self, start: VirtAddr, size: u64) -> impl Iterator<Item = Page> + use<> {
+        let first = Page::containing_address(start);
+        let last = Page::containing_address(start + size.max(1) - 1u64);
+        self.check_private(first);
+        self.check_private(last);
+        Page::range_inclusive(first, last)
+    }
+
+    fn check_private(&self, page: Page) {
+        assert!(
+            !self.shared[usize::from(page.p4_index())],
+            "{page:?} is in a level 4 slot shared with the kernel"
+        );
+    }
+
+    fn flush(&self, flush: MapperFlush<Size4KiB>) {
+        // the TLB only caches entries of the active address space
+        if self.is_active() {
+            flush.flush();
+        } else {
+            flush.ignore();
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Frees all page tables below the private level 4 slots, the frames `map_range` allocated,
+    /// and the level 4 table itself.
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "dropped the active address space");
+
+        let mut frame_allocator = frame_allocator();
+        let level_4_table = unsafe { &*table_ptr(self.level_4_frame) };
+        for (i, entry) in level_4_table.iter().enumerate() {
+            if self.shared[i] || entry.is_unused() {
+                continue;
+            }
+            unsafe { free_table(entry.frame().unwrap(), 3, &mut *frame_allocator) };
+        }
+        unsafe { frame_allocator.deallocate_frame(self.level_4_frame) };
+    }
+}
+
+/// Switches back to the page tables the kernel booted with.
+///
+/// This function is unsafe for the same reasons as `AddressSpace::switch`.
+pub unsafe fn switch_to_kernel() {
+    unsafe { Cr3::write(kernel_level_4_frame(), Cr3Flags::empty()) };
+}
+
+/// Frees a page table at `level` (3 down to 1), everything below it, and owned leaf frames.
+unsafe fn free_table(
+    frame: PhysFrame,
+    level: u8,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+) {
+    let table = unsafe { &*table_ptr(frame) };
+    for entry in table.iter().filter(|entry| !entry.is_unused()) {
+        let Ok(child) = entry.frame() else {
+            continue; // huge pages are never created by AddressSpace
+        };
+        if level > 1 {
+            unsafe { free_table(child, level - 1, frame_allocator) };
+        } else if entry.flags().contains(OWNED) {
+            unsafe { frame_allocator.deallocate_frame(child) };
+        }
+    }
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+fn table_ptr(frame: PhysFrame) -> *mut PageTable {
+    (physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr()
+}
@@ -0,0 +1,166 @@
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use core::slice;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// A frame allocator that tracks every frame between the lowest and highest usable address
+/// in a bitmap, one bit per frame. A set bit means the frame is in use (or not usable at all).
+///
+/// The bitmap itself lives in the first usable region big enough to hold it, and is accessed
+/// through the physical memory offset mapping.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    base: u64,
+    total_frames: usize,
+    free_frames: usize,
+    next_free: usize, // every frame below this index is known to be used
+}
+
+impl BitmapFrameAllocator {
+    /// Creates a BitmapFrameAllocator from the passed memory map.
+    ///
+    /// This function is unsafe because the caller must make sure that the passed memory map is
+    /// valid, that all frames marked as `USABLE` are actually unused, and that all of physical
+    /// memory is mapped at `physical_memory_offset`. Must only be called once.
+    pub unsafe fn init(
+        memory_regions: &'static MemoryRegions,
+        physical_memory_offset: VirtAddr,
+    ) -> Self {
+        let usable = || {
+            memory_regions
+                .iter()
+                .filter(|region| region.kind == MemoryRegionKind::Usable)
+                .map(|region| (align_up(region.start), align_down(region.end)))
+                .filter(|(start, end)| start < end)
+        };
+
+        let base = usable().map(|(start, _)| start).min().unwrap_or(0);
+        let top = usable().map(|(_, end)| end).max().unwrap_or(0);
+        let total_frames = ((top - base) / FRAME_SIZE) as usize;
+        let words = total_frames.div_ceil(BITS_PER_WORD);
+        let bitmap_size = align_up((words * size_of::<u64>()) as u64);
+
+        // steal the start of the first usable region that fits the bitmap
+        let bitmap_start = usable()
+            .find(|(start, end)| end - start >= bitmap_size)
+            .map(|(start, _)| start)
+            .expect("no usable region big enough for the frame bitmap");
+
+        let bitmap = unsafe {
+            let ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+            slice::from_raw_parts_mut(ptr, words)
+        };
+        bitmap.fill(!0);
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            base,
+            total_frames,
+            free_frames: 0,
+            next_free: 0,
+        };
+
+        for (start, end) in usable() {
+            for addr in (start..end).step_by(FRAME_SIZE as usize) {
+                allocator.set_free(allocator.index_of(addr));
+            }
+        }
+        for addr in (bitmap_start..bitmap_start + bitmap_size).step_by(FRAME_SIZE as usize) {
+            allocator.set_used(allocator.index_of(addr));
+        }
+
+        allocator
+    }
+
+    /// Number of usable frames that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Number of frames covered by the bitmap that are currently in use, including the ones
+    /// holding the bitmap itself and any holes between usable regions.
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+
+    /// Number of frames covered by the bitmap.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    fn index_of(&self, addr: u64) -> usize {
+        let offset = addr
+            .checked_sub(self.base)
+            .unwrap_or_else(|| panic!("frame {addr:#x} is below the bitmap at {:#x}", self.base));
+        (offset / FRAME_SIZE) as usize
+    }
+
+    fn is_free(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) == 0
+    }
+
+    fn set_free(&mut self, index: usize) {
+        if !self.is_free(index) {
+            self.bitmap[index / BITS_PER_WORD] &= !(1 << (index % BITS_PER_WORD));
+            self.free_frames += 1;
+            self.next_free = self.next_free.min(index);
+        }
+    }
+
+    fn set_used(&mut self, index: usize) {
+        if self.is_free(index) {
+            self.bitmap[index / BITS_PER_WORD] |= 1 << (index % BITS_PER_WORD);
+            self.free_frames -= 1;
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let start_word = self.next_free / BITS_PER_WORD;
+        // skip full words, then pick the lowest clear bit of the first one with space
+        let (word_index, word) = self.bitmap[start_word..]
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != !0)
+            .map(|(i, word)| (start_word + i, *word))?;
+
+        let index = word_index * BITS_PER_WORD + (!word).trailing_zeros() as usize;
+        if index >= self.total_frames {
+            return None;
+        }
+
+        self.set_used(index);
+        self.next_free = index + 1;
+        let addr = self.base + index as u64 * FRAME_SIZE;
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let index = self.index_of(frame.start_address().as_u64());
+        assert!(
+            index < self.total_frames,
+            "deallocated frame outside of the bitmap: {frame:?}"
+        );
+        assert!(
+            !self.is_free(index),
+            "double free of physical frame {frame:?}"
+        );
+        self.set_free(index);
+    }
+}
+
+fn align_up(addr: u64) -> u64 {
+    (addr + FRAME_SIZE - 1) & !(FRAME_SIZE - 1)
+}
+
+fn align_down(addr: u64) -> u64 {
+    addr & !(FRAME_SIZE - 1)
+}
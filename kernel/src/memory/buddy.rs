@@ -0,0 +1,255 @@
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use core::slice;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB},
+};
+
+/// Order 0 is a single 4 KiB frame, order 9 a 2 MiB frame and order 18 a 1 GiB frame.
+pub const MAX_ORDER: usize = 18;
+const ORDERS: usize = MAX_ORDER + 1;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// Stored at the start of every free block, links to its neighbours in the list of its order.
+struct FreeBlock {
+    next: Option<u64>,
+    prev: Option<u64>,
+}
+
+/// A buddy-system physical frame allocator.
+///
+/// Free blocks of `4 KiB << order` bytes are kept in one intrusive list per order, written into
+/// the free frames themselves through the physical memory offset mapping. Allocating splits the
+/// smallest big-enough block in halves, freeing merges a block with its buddy whenever the buddy
+/// is free too.
+///
+/// Which blocks are free is also kept in one bitmap per order, so checking a buddy doesn't walk
+/// its list. The bitmaps cover everything below the highest usable address and live at the
+/// start of the first usable region big enough to hold them.
+pub struct BuddyFrameAllocator {
+    physical_memory_offset: VirtAddr,
+    free_lists: [Option<u64>; ORDERS],
+    free_map: &'static mut [u64],
+    map_offsets: [usize; ORDERS], // first word of each order's bitmap in `free_map`
+    total_frames: usize,
+    free_frames: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// Creates a BuddyFrameAllocator from the passed memory map.
+    ///
+    /// This function is unsafe because the caller must make sure that the passed memory map is
+    /// valid, that all frames marked as `USABLE` are actually unused, and that all of physical
+    /// memory is mapped at `physical_memory_offset`. Must only be called once.
+    pub unsafe fn init(
+        memory_regions: &'static MemoryRegions,
+        physical_memory_offset: VirtAddr,
+    ) -> Self {
+        let usable = || {
+            memory_regions
+                .iter()
+                .filter(|region| region.kind == MemoryRegionKind::Usable)
+                .map(|region| (align_up(region.start, block_size(0)), region.end))
+                .filter(|(start, end)| start + block_size(0) <= *end)
+        };
+
+        let top = usable().map(|(_, end)| end).max().unwrap_or(0);
+        let mut map_offsets = [0; ORDERS];
+        let mut words = 0;
+        for (order, offset) in map_offsets.iter_mut().enumerate() {
+            *offset = words;
+            words += (top / block_size(order) + 1).div_ceil(BITS_PER_WORD as u64) as usize;
+        }
+        let map_size = align_up((words * size_of::<u64>()) as u64, block_size(0));
+
+        // steal the start of the first usable region that fits the bitmaps
+        let map_start = usable()
+            .find(|(start, end)| end - start >= map_size)
+            .map(|(start, _)| start)
+            .expect("no usable region big enough for the buddy bitmaps");
+
+        let free_map = unsafe {
+            let ptr: *mut u64 = (physical_memory_offset + map_start).as_mut_ptr();
+            slice::from_raw_parts_mut(ptr, words)
+        };
+        free_map.fill(0);
+
+        let mut allocator = BuddyFrameAllocator {
+            physical_memory_offset,
+            free_lists: [None; ORDERS],
+            free_map,
+            map_offsets,
+            total_frames: 0,
+            free_frames: 0,
+        };
+
+        for (mut start, end) in usable() {
+            if start == map_start {
+                start += map_size;
+            }
+            // carve the region into the biggest naturally aligned blocks that fit
+            while start + block_size(0) <= end {
+                let order = (0..=MAX_ORDER)
+                    .rev()
+                    .find(|&order| {
+                        start % block_size(order) == 0 && start + block_size(order) <= end
+                    })
+                    .unwrap();
+                unsafe { allocator.push(start, order) };
+                allocator.total_frames += 1 << order;
+                allocator.free_frames += 1 << order;
+                start += block_size(order);
+            }
+        }
+
+        allocator
+    }
+
+    /// Number of free 4 KiB frames.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Number of allocated 4 KiB frames.
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+
+    /// Number of usable 4 KiB frames handed to the allocator at boot.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Number of free blocks of the given order.
+    pub fn free_blocks(&self, order: usize) -> usize {
+        let mut count = 0;
+        let mut current = self.free_lists[order];
+        while let Some(addr) = current {
+            count += 1;
+            current = unsafe { (*self.block(addr)).next };
+        }
+        count
+    }
+
+    /// Allocates a block of `4 KiB << order` bytes, splitting a bigger one if needed.
+    fn allocate(&mut self, order: usize) -> Option<u64> {
+        let mut current = (order..=MAX_ORDER).find(|&o| self.free_lists[o].is_some())?;
+        let addr = unsafe { self.pop(current) }?;
+
+        // give the upper halves back until the block is the requested size
+        while current > order {
+            current -= 1;
+            unsafe { self.push(addr + block_size(current), current) };
+        }
+
+        self.free_frames -= 1 << order;
+        Some(addr)
+    }
+
+    /// Frees a block of `4 KiB << order` bytes, merging it with its buddy as far as possible.
+    ///
+    /// This function is unsafe because the caller must make sure that the block was allocated
+    /// from this allocator and is no longer in use.
+    unsafe fn free(&mut self, mut addr: u64, order: usize) {
+        self.free_frames += 1 << order;
+
+        let mut current = order;
+        while current < MAX_ORDER {
+            let buddy = addr ^ block_size(current);
+            if !self.is_free(buddy, current) {
+                break;
+            }
+            unsafe { self.remove(buddy, current) };
+            addr = addr.min(buddy);
+            current += 1;
+        }
+
+        unsafe { self.push(addr, current) };
+    }
+
+    fn block(&self, addr: u64) -> *mut FreeBlock {
+        (self.physical_memory_offset + addr).as_mut_ptr()
+    }
+
+    /// Word index and bit mask of the block at `addr` in the bitmap of `order`.
+    fn map_bit(&self, addr: u64, order: usize) -> (usize, u64) {
+        let index = (addr / block_size(order)) as usize;
+        (
+            self.map_offsets[order] + index / BITS_PER_WORD,
+            1 << (index % BITS_PER_WORD),
+        )
+    }
+
+    fn is_free(&self, addr: u64, order: usize) -> bool {
+        let (word, mask) = self.map_bit(addr, order);
+        // the bitmaps end at the highest usable address, nothing above it is ever free
+        let end = self.map_offsets.get(order + 1).copied();
+        if word >= end.unwrap_or(self.free_map.len()) {
+            return false;
+        }
+        self.free_map[word] & mask != 0
+    }
+
+    unsafe fn push(&mut self, addr: u64, order: usize) {
+        let next = self.free_lists[order].take();
+        unsafe {
+            *self.block(addr) = FreeBlock { next, prev: None };
+            if let Some(next) = next {
+                (*self.block(next)).prev = Some(addr);
+            }
+        }
+        self.free_lists[order] = Some(addr);
+
+        let (word, mask) = self.map_bit(addr, order);
+        self.free_map[word] |= mask;
+    }
+
+    unsafe fn pop(&mut self, order: usize) -> Option<u64> {
+        let addr = self.free_lists[order]?;
+        unsafe { self.remove(addr, order) };
+        Some(addr)
+    }
+
+    /// Unlinks the free block at `addr` from the list of `order`.
+    ///
+    /// This function is unsafe because the caller must make sure that the block is free and of
+    /// that order, see `is_free`.
+    unsafe fn remove(&mut self, addr: u64, order: usize) {
+        let FreeBlock { next, prev } = unsafe { self.block(addr).read() };
+        match prev {
+            Some(prev) => unsafe { (*self.block(prev)).next = next },
+            None => self.free_lists[order] = next,
+        }
+        if let Some(next) = next {
+            unsafe { (*self.block(next)).prev = prev };
+        }
+
+        let (word, mask) = self.map_bit(addr, order);
+        self.free_map[word] &= !mask;
+    }
+}
+
+unsafe impl<S: PageSize> FrameAllocator<S> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>> {
+        let addr = self.allocate(order_of::<S>())?;
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        unsafe { self.free(frame.start_address().as_u64(), order_of::<S>()) };
+    }
+}
+
+fn order_of<S: PageSize>() -> usize {
+    (S::SIZE / Size4KiB::SIZE).trailing_zeros() as usize
+}
+
+fn block_size(order: usize) -> u64 {
+    Size4KiB::SIZE << order
+}
+
+fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) & !(align - 1)
+}
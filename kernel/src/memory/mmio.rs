@@ -0,0 +1,200 @@
+use core::fmt;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    registers::model_specific::Msr,
+    structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, mapper::MapToError},
+};
+
+use super::{frame_allocator, mapper, virt};
+
+const IA32_PAT: u32 = 0x277;
+
+/// The PAT layout the kernel runs with, one byte per entry. Entries 0 to 3 only need the PWT and
+/// PCD bits, so those hold the types `CachePolicy` uses. The power-on default has write-through
+/// in entry 1, here it's write-combining and write-through moves up to entry 7.
+///
+/// | entry | PAT PCD PWT | type |
+/// |-------|-------------|------|
+/// | 0     | 0 0 0       | WB   |
+/// | 1     | 0 0 1       | WC   |
+/// | 2     | 0 1 0       | UC-  |
+/// | 3     | 0 1 1       | UC   |
+/// | 4     | 1 0 0       | WB   |
+/// | 5     | 1 0 1       | WP   |
+/// | 6     | 1 1 0       | UC-  |
+/// | 7     | 1 1 1       | WT   |
+const PAT_VALUE: u64 = 0x0407_0506_0007_0106;
+
+/// How the CPU may cache accesses to a mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Normal memory.
+    WriteBack,
+    /// Writes get buffered and combined into bursts, reads aren't cached. For framebuffers.
+    WriteCombining,
+    /// Every access goes straight to the device, in order. For registers.
+    Uncacheable,
+}
+
+impl CachePolicy {
+    /// The page table bits that select this policy's PAT entry.
+    pub fn flags(self) -> PageTableFlags {
+        match self {
+            CachePolicy::WriteBack => PageTableFlags::empty(),
+            CachePolicy::WriteCombining => PageTableFlags::WRITE_THROUGH,
+            CachePolicy::Uncacheable => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+        }
+    }
+}
+
+/// Loads the kernel's PAT layout. Called from `memory::init`.
+///
+/// This function is unsafe because changing a PAT entry changes the caching of every mapping
+/// already using it. Nothing the bootloader sets up uses the PWT bit, so this is fine as long as
+/// it runs before anything else maps memory with it.
+pub(super) unsafe fn init_pat() {
+    unsafe { Msr::new(IA32_PAT).write(PAT_VALUE) };
+    x86_64::instructions::tlb::flush_all();
+}
+
+/// Why `map_mmio` failed.
+#[derive(Debug)]
+pub enum MmioError {
+    /// The kernel virtual range allocator is out of space.
+    OutOfAddressSpace,
+    Map(MapToError<Size4KiB>),
+}
+
+impl fmt::Display for MmioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MmioError::OutOfAddressSpace => write!(f, "out of kernel virtual address space"),
+            MmioError::Map(err) => write!(f, "mapping failed: {err:?}"),
+        }
+    }
+}
+
+/// A device's physical memory mapped into kernel virtual memory. Unmapped again on drop.
+#[derive(Debug)]
+pub struct MmioRegion {
+    phys: PhysAddr,
+    len: usize,
+    /// Where the first mapped page starts, `phys` may sit somewhere inside it.
+    page_start: VirtAddr,
+    mapped_size: u64,
+    policy: CachePolicy,
+}
+
+/// Maps `len` bytes of device memory at `phys` as uncacheable.
+///
+/// This function is unsafe for the same reasons as `map_mmio_with`.
+pub unsafe fn map_mmio(phys: PhysAddr, len: usize) -> Result<MmioRegion, MmioError> {
+    unsafe { map_mmio_with(phys, len, CachePolicy::Uncacheable) }
+}
+
+/// Maps `len` bytes of device memory at `phys` with the given caching policy.
+///
+/// This function is unsafe because the caller must make sure `phys` is really device memory (or
+/// memory nobody else is using), and that mapping it with `policy` doesn't conflict with how it's
+/// mapped elsewhere. Note that the physical memory mapping covers everything with write-back, so
+/// RAM shouldn't be mapped as anything else while it's being accessed through there.
+pub unsafe fn map_mmio_with(
+    phys: PhysAddr,
+    len: usize,
+    policy: CachePolicy,
+) -> Result<MmioRegion, MmioError> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last_frame = PhysFrame::<Size4KiB>::containing_address(phys + len.max(1) as u64 - 1u64);
+    let mapped_size = last_frame - first_frame + 4096;
+
+    let page_start = virt::allocate(mapped_size, 4096).ok_or(MmioError::OutOfAddressSpace)?;
+    // built before mapping so a failure halfway gets cleaned up by the drop
+    let region = MmioRegion {
+        phys,
+        len,
+        page_start,
+        mapped_size,
+        policy,
+    };
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | policy.flags();
+    let mut mapper = mapper();
+    let mut frame_allocator = frame_allocator();
+    let first_page = Page::<Size4KiB>::containing_address(page_start);
+    for (i, frame) in PhysFrame::range_inclusive(first_frame, last_frame).enumerate() {
+        let page = first_page + i as u64;
+        unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator) }
+            .map_err(MmioError::Map)?
+            .flush();
+    }
+    Ok(region)
+}
+
+impl MmioRegion {
+    /// The virtual address `phys_addr` is mapped at.
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.page_start + self.phys.as_u64() % 4096
+    }
+
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn policy(&self) -> CachePolicy {
+        self.policy
+    }
+
+    /// A pointer `offset` bytes into the region.
+    pub fn as_mut_ptr<T>(&self, offset: usize) -> *mut T {
+        self.check(offset, size_of::<T>());
+        (self.virt_addr() + offset as u64).as_mut_ptr()
+    }
+
+    /// Does a volatile read of the `T` at `offset`, which has to be in bounds and aligned.
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        let ptr: *const T = self.as_mut_ptr(offset);
+        assert!(ptr.is_aligned(), "unaligned mmio read at {offset:#x}");
+        unsafe { ptr.read_volatile() }
+    }
+
+    /// Does a volatile write of `value` at `offset`, which has to be in bounds and aligned.
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        let ptr: *mut T = self.as_mut_ptr(offset);
+        assert!(ptr.is_aligned(), "unaligned mmio write at {offset:#x}");
+        unsafe { ptr.write_volatile(value) }
+    }
+
+    fn check(&self, offset: usize, size: usize) {
+        assert!(
+            offset.checked_add(size).is_some_and(|end| end <= self.len),
+            "mmio access at {offset:#x} out of bounds for region of {:#x} bytes",
+            self.len
+        );
+    }
+}
+
+impl Drop for MmioRegion {
+    /// Unmaps the region and gives back its virtual range. The frames belong to the device, so
+    /// they don't go back to the frame allocator.
+    fn drop(&mut self) {
+        let mut mapper = mapper();
+        let first = Page::<Size4KiB>::containing_address(self.page_start);
+        for page in Page::range(first, first + self.mapped_size / 4096) {
+            if let Ok((_, flush)) = mapper.unmap(page) {
+                flush.flush();
+            }
+        }
+        virt::free(self.page_start, self.mapped_size);
+    }
+}
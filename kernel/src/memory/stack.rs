@@ -0,0 +1,109 @@
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{
+        FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB, Translate, mapper::MapToError,
+    },
+};
+
+use super::{frame_allocator, mapper};
+
+/// Kernel stacks allocated by `allocate` live here, each one above its own unmapped guard page.
+pub const STACK_REGION_START: u64 = 0x_5555_0000_0000;
+
+/// A kernel stack with an unmapped guard page right below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelStack {
+    pub name: &'static str,
+    pub guard: Page,
+    pub bottom: VirtAddr,
+    /// Stacks grow down, so this is what goes in RSP or the IST.
+    pub top: VirtAddr,
+}
+
+impl KernelStack {
+    pub fn size(&self) -> u64 {
+        self.top - self.bottom
+    }
+}
+
+static STACKS: Mutex<Vec<KernelStack>> = Mutex::new(Vec::new());
+static NEXT_STACK: Mutex<u64> = Mutex::new(STACK_REGION_START);
+
+/// Maps a new kernel stack of `size` bytes (rounded up to whole pages) with a guard page below.
+pub fn allocate(name: &'static str, size: u64) -> Result<KernelStack, MapToError<Size4KiB>> {
+    let pages = size.div_ceil(4096);
+    let guard = {
+        let mut next = NEXT_STACK.lock();
+        let guard = Page::containing_address(VirtAddr::new(*next));
+        *next += (pages + 1) * 4096;
+        guard
+    };
+
+    let mut mapper = mapper();
+    let mut frame_allocator = frame_allocator();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for page in Page::range(guard + 1, guard + 1 + pages) {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator)?.flush() };
+    }
+
+    let stack = KernelStack {
+        name,
+        guard,
+        bottom: (guard + 1).start_address(),
+        top: (guard + 1 + pages).start_address(),
+    };
+    STACKS.lock().push(stack);
+    Ok(stack)
+}
+
+/// Registers the stack we're currently running on (the one the bootloader set up), by walking the
+/// page tables out from RSP until it hits the unmapped pages around it.
+pub fn register_current(name: &'static str) -> KernelStack {
+    let rsp: u64;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp) };
+
+    let mapper = mapper();
+    let is_mapped = |page: Page| mapper.translate_addr(page.start_address()).is_some();
+    let current = Page::<Size4KiB>::containing_address(VirtAddr::new(rsp));
+    let mut bottom = current;
+    while is_mapped(bottom - 1) {
+        bottom -= 1;
+    }
+    let mut top = current + 1;
+    while is_mapped(top) {
+        top += 1;
+    }
+
+    let stack = KernelStack {
+        name,
+        guard: bottom - 1,
+        bottom: bottom.start_address(),
+        top: top.start_address(),
+    };
+    STACKS.lock().push(stack);
+    stack
+}
+
+/// The stack whose guard page contains `addr`, for fault handlers to tell a stack overflow from
+/// any other bad access. Returns `None` if the stack list is locked, as faults can't wait on it.
+pub fn overflowed_stack(addr: VirtAddr) -> Option<KernelStack> {
+    let stacks = STACKS.try_lock()?;
+    stacks
+        .iter()
+        .find(|stack| Page::containing_address(addr) == stack.guard)
+        .copied()
+}
+
+/// The stack `addr` is on, if it's one we know about.
+pub fn containing(addr: VirtAddr) -> Option<KernelStack> {
+    let stacks = STACKS.try_lock()?;
+    stacks
+        .iter()
+        .find(|stack| (stack.bottom..stack.top).contains(&addr))
+        .copied()
+}
@@ -0,0 +1,102 @@
+use alloc::{format, vec::Vec};
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use core::fmt;
+
+/// A snapshot of physical memory and heap usage, from `memory::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Bytes covered by the bootloader's memory map, whatever their kind.
+    pub total_bytes: u64,
+    /// Bytes the memory map marks usable, i.e. what the frame allocator started out with.
+    pub usable_bytes: u64,
+    pub total_frames: usize,
+    pub allocated_frames: usize,
+    pub free_frames: usize,
+    /// Bytes currently mapped for the heap.
+    pub heap_size: usize,
+    /// Bytes handed out by the global allocator.
+    pub heap_used: usize,
+    /// Bytes left in the heap before it has to grow.
+    pub heap_free: usize,
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "physical: {} total, {} usable",
+            Size(self.total_bytes),
+            Size(self.usable_bytes)
+        )?;
+        writeln!(
+            f,
+            "frames:   {} allocated, {} free, {} total",
+            self.allocated_frames, self.free_frames, self.total_frames
+        )?;
+        write!(
+            f,
+            "heap:     {} mapped, {} used, {} free",
+            Size(self.heap_size as u64),
+            Size(self.heap_used as u64),
+            Size(self.heap_free as u64)
+        )
+    }
+}
+
+/// Formats the bootloader's memory map as a table of regions followed by the totals per kind.
+///
+/// Needs the heap.
+pub struct MemoryMap<'a>(pub &'a MemoryRegions);
+
+impl fmt::Display for MemoryMap<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<16} {:<37} {:>10}", "kind", "range", "size")?;
+        let mut totals: Vec<(MemoryRegionKind, u64, usize)> = Vec::new();
+        for region in self.0.iter() {
+            let size = region.end - region.start;
+            writeln!(
+                f,
+                "{:<16} {:#018x}-{:#018x} {:>10}",
+                format!("{:?}", region.kind),
+                region.start,
+                region.end,
+                format!("{}", Size(size))
+            )?;
+
+            match totals.iter_mut().find(|(kind, ..)| *kind == region.kind) {
+                Some((_, bytes, count)) => {
+                    *bytes += size;
+                    *count += 1;
+                }
+                None => totals.push((region.kind, size, 1)),
+            }
+        }
+
+        write!(f, "totals:")?;
+        for (kind, bytes, count) in totals {
+            write!(
+                f,
+                "\n{:<16} {:<37} {:>10}",
+                format!("{kind:?}"),
+                format!("{count} regions"),
+                format!("{}", Size(bytes))
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A byte count in the biggest binary unit it has at least one of.
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (value, unit) = match self.0 {
+            bytes if bytes >= 1 << 30 => (bytes >> 30, "GiB"),
+            bytes if bytes >= 1 << 20 => (bytes >> 20, "MiB"),
+            bytes if bytes >= 1 << 10 => (bytes >> 10, "KiB"),
+            bytes => (bytes, "B"),
+        };
+        write!(f, "{value} {unit}")
+    }
+}
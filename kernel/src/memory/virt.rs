@@ -0,0 +1,122 @@
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+/// Kernel virtual memory handed out by `allocate`, for mappings that don't live at a fixed
+/// address (MMIO regions and the like).
+pub const VIRT_REGION_START: u64 = 0x_6666_0000_0000;
+pub const VIRT_REGION_SIZE: u64 = 64 * 1024 * 1024 * 1024; // 64 GiB
+
+/// Hands out page aligned ranges of virtual addresses, without mapping anything.
+///
+/// Ranges are bumped off the end of the region until something is freed, after that freed
+/// ranges are reused first-fit. Neighbouring free ranges are merged, and a free range touching
+/// the bump pointer gives its space back to it.
+pub struct VirtualRangeAllocator {
+    end: u64,
+    next: u64,
+    /// Free ranges below `next`, start -> size.
+    free: BTreeMap<u64, u64>,
+}
+
+impl VirtualRangeAllocator {
+    /// Creates an allocator for the `size` bytes starting at `start`.
+    pub const fn new(start: u64, size: u64) -> Self {
+        VirtualRangeAllocator {
+            end: start + size,
+            next: start,
+            free: BTreeMap::new(),
+        }
+    }
+
+    /// Allocates `size` bytes (rounded up to whole pages) aligned to `align`, which has to be a
+    /// power of two of at least 4 KiB.
+    pub fn allocate(&mut self, size: u64, align: u64) -> Option<VirtAddr> {
+        assert!(align.is_power_of_two() && align >= 4096);
+        let size = size.max(1).next_multiple_of(4096);
+
+        let fit = self.free.iter().find_map(|(&start, &free_size)| {
+            let aligned = start.next_multiple_of(align);
+            (aligned + size <= start + free_size).then_some((start, free_size, aligned))
+        });
+        if let Some((start, free_size, aligned)) = fit {
+            self.free.remove(&start);
+            if aligned > start {
+                self.free.insert(start, aligned - start);
+            }
+            let end = start + free_size;
+            if aligned + size < end {
+                self.free.insert(aligned + size, end - aligned - size);
+            }
+            return Some(VirtAddr::new(aligned));
+        }
+
+        let aligned = self.next.next_multiple_of(align);
+        if aligned + size > self.end {
+            return None;
+        }
+        if aligned > self.next {
+            self.free.insert(self.next, aligned - self.next);
+        }
+        self.next = aligned + size;
+        Some(VirtAddr::new(aligned))
+    }
+
+    /// Gives back a range returned by `allocate`, with the same size it was allocated with.
+    pub fn free(&mut self, start: VirtAddr, size: u64) {
+        let mut start = start.as_u64();
+        let mut size = size.max(1).next_multiple_of(4096);
+        assert!(
+            start % 4096 == 0 && start + size <= self.next,
+            "freed a range never allocated"
+        );
+
+        if let Some((&prev, &prev_size)) = self.free.range(..start).next_back() {
+            assert!(prev + prev_size <= start, "double free of {start:#x}");
+            if prev + prev_size == start {
+                self.free.remove(&prev);
+                start = prev;
+                size += prev_size;
+            }
+        }
+        if let Some((&next, &next_size)) = self.free.range(start..).next() {
+            assert!(start + size <= next, "double free of {start:#x}");
+            if start + size == next {
+                self.free.remove(&next);
+                size += next_size;
+            }
+        }
+
+        if start + size == self.next {
+            self.next = start;
+        } else {
+            self.free.insert(start, size);
+        }
+    }
+
+    /// Bytes that can still be allocated, though maybe not in one piece.
+    pub fn free_bytes(&self) -> u64 {
+        self.end - self.next + self.free.values().sum::<u64>()
+    }
+}
+
+static KERNEL_RANGES: Mutex<VirtualRangeAllocator> = Mutex::new(VirtualRangeAllocator::new(
+    VIRT_REGION_START,
+    VIRT_REGION_SIZE,
+));
+
+/// Allocates `size` bytes of kernel virtual address space aligned to `align`. Nothing gets
+/// mapped, that's up to the caller.
+pub fn allocate(size: u64, align: u64) -> Option<VirtAddr> {
+    KERNEL_RANGES.lock().allocate(size, align)
+}
+
+/// Frees a range from `allocate`. The caller has to have unmapped it already.
+pub fn free(start: VirtAddr, size: u64) {
+    KERNEL_RANGES.lock().free(start, size)
+}
+
+/// Bytes of kernel virtual address space still free.
+pub fn free_bytes() -> u64 {
+    KERNEL_RANGES.lock().free_bytes()
+}
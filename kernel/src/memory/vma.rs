@@ -0,0 +1,185 @@
+use alloc::collections::BTreeMap;
+use core::fmt;
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+        },
+    },
+};
+
+use super::{frame_allocator, mapper, physical_memory_offset, try_lock};
+
+/// What a page of an area gets filled with when it's first touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Zeroed memory, allocated on the first access.
+    Anonymous,
+}
+
+/// A reserved range of kernel virtual memory whose pages get mapped when they're first touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualMemoryArea {
+    pub start: VirtAddr,
+    pub size: u64,
+    /// The flags the pages get mapped with, `WRITABLE` and `NO_EXECUTE` also decide which
+    /// accesses are allowed.
+    pub flags: PageTableFlags,
+    pub backing: Backing,
+}
+
+impl VirtualMemoryArea {
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        let first = Page::containing_address(self.start);
+        let last = Page::containing_address(self.end() - 1u64);
+        Page::range_inclusive(first, last)
+    }
+}
+
+/// Why a page fault couldn't be resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultError {
+    /// The address isn't inside any area.
+    NoArea,
+    /// The page is mapped, so the fault came from breaking its protection.
+    ProtectionViolation(VirtualMemoryArea),
+    /// The area doesn't allow this kind of access.
+    AccessDenied(VirtualMemoryArea),
+    OutOfFrames,
+    /// The fault happened while the area list, the mapper or the frame allocator was locked.
+    Busy,
+}
+
+impl fmt::Display for FaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultError::NoArea => write!(f, "address is not in any memory area"),
+            FaultError::ProtectionViolation(area) => {
+                write!(f, "protection violation in area {area:?}")
+            }
+            FaultError::AccessDenied(area) => write!(f, "access not allowed by area {area:?}"),
+            FaultError::OutOfFrames => write!(f, "out of physical frames"),
+            FaultError::Busy => write!(f, "memory structures locked while faulting"),
+        }
+    }
+}
+
+/// Areas by start address.
+static AREAS: Mutex<BTreeMap<u64, VirtualMemoryArea>> = Mutex::new(BTreeMap::new());
+
+/// Reserves `size` bytes at `start` (both page aligned) without backing them yet.
+///
+/// Returns the overlapping area if the range is already (partly) reserved.
+pub fn reserve(
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    backing: Backing,
+) -> Result<VirtualMemoryArea, VirtualMemoryArea> {
+    assert!(start.is_aligned(4096u64) && size % 4096 == 0 && size > 0);
+    let area = VirtualMemoryArea {
+        start,
+        size,
+        flags: flags | PageTableFlags::PRESENT,
+        backing,
+    };
+
+    let mut areas = AREAS.lock();
+    let overlapping = areas
+        .range(..area.end().as_u64())
+        .next_back()
+        .map(|(_, other)| *other)
+        .filter(|other| other.end() > start);
+    if let Some(other) = overlapping {
+        return Err(other);
+    }
+    areas.insert(start.as_u64(), area);
+    Ok(area)
+}
+
+/// Removes the area starting at `start`, unmapping and freeing any pages that were touched.
+pub fn release(start: VirtAddr) -> Option<VirtualMemoryArea> {
+    let area = AREAS.lock().remove(&start.as_u64())?;
+
+    let mut mapper = mapper();
+    let mut frame_allocator = frame_allocator();
+    for page in area.pages() {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+    }
+    Some(area)
+}
+
+/// The area containing `addr`, if there is one.
+pub fn find(addr: VirtAddr) -> Option<VirtualMemoryArea> {
+    find_in(&AREAS.lock(), addr)
+}
+
+fn find_in(areas: &BTreeMap<u64, VirtualMemoryArea>, addr: VirtAddr) -> Option<VirtualMemoryArea> {
+    areas
+        .range(..=addr.as_u64())
+        .next_back()
+        .map(|(_, area)| *area)
+        .filter(|area| area.contains(addr))
+}
+
+/// Called by the page fault handler, maps the faulting page if it belongs to an area.
+///
+/// Must not block or allocate, so every lock is only tried.
+pub(crate) fn handle_page_fault(
+    addr: VirtAddr,
+    error_code: PageFaultErrorCode,
+) -> Result<(), FaultError> {
+    let area = {
+        let areas = AREAS.try_lock().ok_or(FaultError::Busy)?;
+        find_in(&areas, addr).ok_or(FaultError::NoArea)?
+    };
+
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return Err(FaultError::ProtectionViolation(area));
+    }
+    let denied = (error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        && !area.flags.contains(PageTableFlags::WRITABLE))
+        || (error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+            && area.flags.contains(PageTableFlags::NO_EXECUTE));
+    if denied {
+        return Err(FaultError::AccessDenied(area));
+    }
+
+    let (mut mapper, mut frame_allocator) = try_lock().ok_or(FaultError::Busy)?;
+    let frame: PhysFrame<Size4KiB> = frame_allocator
+        .allocate_frame()
+        .ok_or(FaultError::OutOfFrames)?;
+    match area.backing {
+        Backing::Anonymous => unsafe {
+            let ptr: *mut u8 =
+                (physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr();
+            ptr.write_bytes(0, 4096);
+        },
+    }
+
+    let page = Page::containing_address(addr);
+    match unsafe { mapper.map_to(page, frame, area.flags, &mut *frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            Ok(())
+        }
+        Err(_) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            Err(FaultError::OutOfFrames)
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Turning the machine off or resetting it.
+
+use core::fmt;
+use x86_64::VirtAddr;
+use x86_64::instructions::{interrupts, port::Port, tables::lidt};
+use x86_64::structures::DescriptorTablePointer;
+
+use crate::acpi::{
+    self, AcpiError,
+    aml::{self, SleepType},
+    fadt::Fadt,
+};
+
+// PM1 control register bits
+const SCI_EN: u64 = 1;
+const SLP_TYP_SHIFT: u64 = 10;
+const SLP_TYP_MASK: u64 = 0b111 << SLP_TYP_SHIFT;
+const SLP_EN: u64 = 1 << 13;
+
+const KEYBOARD_CONTROLLER_STATUS: u16 = 0x64;
+const KEYBOARD_CONTROLLER_INPUT_FULL: u8 = 1 << 1;
+const KEYBOARD_CONTROLLER_PULSE_RESET: u8 = 0xfe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerError {
+    Acpi(AcpiError),
+    /// The FADT doesn't say where the PM1a control register is.
+    NoPm1Control,
+    /// The firmware never handed the machine over to ACPI.
+    AcpiModeTimeout,
+    /// Everything was written, but the machine is still running.
+    StillRunning,
+}
+
+impl fmt::Display for PowerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerError::Acpi(err) => write!(f, "{err}"),
+            PowerError::NoPm1Control => write!(f, "no PM1a control register"),
+            PowerError::AcpiModeTimeout => write!(f, "timed out enabling ACPI mode"),
+            PowerError::StillRunning => write!(f, "machine didn't power off"),
+        }
+    }
+}
+
+/// Powers the machine off through ACPI (sleep state S5).
+///
+/// If that doesn't work the CPU halts with interrupts off, so it's safe to pull the plug.
+pub fn shutdown() -> ! {
+    log::info!("power: shutting down");
+    crate::serial::flush();
+    interrupts::disable();
+
+    let err = acpi_shutdown().unwrap_err();
+    log::error!("power: can't power off ({err}), it's now safe to turn off your computer");
+    crate::serial::flush();
+    crate::hlt_loop(); // interrupts are off, so this never wakes up (bar an NMI)
+}
+
+/// Resets the machine, trying the ACPI reset register, then the 8042 keyboard controller, and
+/// as a last resort a triple fault.
+pub fn reboot() -> ! {
+    log::info!("power: rebooting");
+    crate::serial::flush();
+    interrupts::disable();
+
+    if let Ok(fadt) = Fadt::get()
+        && let Some(reset) = fadt.reset_register
+    {
+        unsafe { reset.write(u64::from(fadt.reset_value)) };
+        io_delay(100_000);
+        log::warn!("power: ACPI reset register did nothing");
+    }
+
+    // the 8042 is wired to the CPU's reset line, which is why this works at all
+    let mut status = Port::<u8>::new(KEYBOARD_CONTROLLER_STATUS);
+    for _ in 0..100_000 {
+        if unsafe { status.read() } & KEYBOARD_CONTROLLER_INPUT_FULL == 0 {
+            break;
+        }
+    }
+    unsafe { status.write(KEYBOARD_CONTROLLER_PULSE_RESET) };
+    io_delay(100_000);
+    log::warn!("power: keyboard controller reset did nothing");
+    crate::serial::flush();
+
+    // with an empty IDT the breakpoint can't be delivered, the double fault can't either, so the
+    // CPU gives up and resets
+    unsafe {
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        });
+    }
+    x86_64::instructions::interrupts::int3();
+    crate::hlt_loop();
+}
+
+/// The SLP_TYP values for S5 from the DSDT's `\_S5` package.
+///
+/// If the package can't be decoded it falls back to known values: 0 on QEMU/Bochs (the
+/// PIIX4 and ICH9 emulation) and 7 on everything else, which is what Intel's ICH and PCH use.
+pub fn s5_sleep_type() -> Result<SleepType, AcpiError> {
+    let fadt = Fadt::get()?;
+    let dsdt = acpi::table_bytes(fadt.dsdt);
+    if let Some(sleep_type) = aml::sleep_type(&dsdt[acpi::SDT_HEADER_SIZE..], 5) {
+        return Ok(sleep_type);
+    }
+
+    let oem_id = acpi::header_at(fadt.dsdt).oem_id;
+    let fallback = if &oem_id == b"BOCHS " { 0 } else { 7 };
+    log::warn!("power: couldn't find \\_S5 in the DSDT, guessing SLP_TYP {fallback}");
+    Ok(SleepType {
+        a: fallback,
+        b: fallback,
+    })
+}
+
+/// Returns only if powering off failed.
+fn acpi_shutdown() -> Result<(), PowerError> {
+    let fadt = Fadt::get().map_err(PowerError::Acpi)?;
+    let pm1a = fadt.pm1a_control_block.ok_or(PowerError::NoPm1Control)?;
+    let sleep_type = s5_sleep_type().map_err(PowerError::Acpi)?;
+    enable_acpi_mode(&fadt)?;
+
+    unsafe {
+        // SLP_TYP has to be in place before SLP_EN, and b (if there is one) before a
+        if let Some(pm1b) = fadt.pm1b_control_block {
+            let value = pm1b.read() & !SLP_TYP_MASK;
+            pm1b.write(value | u64::from(sleep_type.b) << SLP_TYP_SHIFT | SLP_EN);
+        }
+        let value = pm1a.read() & !SLP_TYP_MASK;
+        pm1a.write(value | u64::from(sleep_type.a) << SLP_TYP_SHIFT | SLP_EN);
+    }
+
+    io_delay(100_000);
+    Err(PowerError::StillRunning)
+}
+
+/// Switches from legacy (SMM) mode to ACPI mode, if the firmware didn't already.
+fn enable_acpi_mode(fadt: &Fadt) -> Result<(), PowerError> {
+    let pm1a = fadt.pm1a_control_block.ok_or(PowerError::NoPm1Control)?;
+    let enabled = || unsafe { pm1a.read() } & SCI_EN != 0;
+    if enabled() {
+        return Ok(());
+    }
+    // no SMI command port means there's only ACPI mode
+    let Some(port) = fadt.smi_command_port else {
+        return Ok(());
+    };
+    if fadt.acpi_enable == 0 {
+        return Ok(());
+    }
+
+    unsafe { Port::<u8>::new(port as u16).write(fadt.acpi_enable) };
+    for _ in 0..1_000 {
+        if enabled() {
+            return Ok(());
+        }
+        io_delay(1_000);
+    }
+    Err(PowerError::AcpiModeTimeout)
+}
+
+/// Waits roughly `iterations` microseconds, each write to the POST port takes about that long.
+fn io_delay(iterations: usize) {
+    let mut post = Port::<u8>::new(0x80);
+    for _ in 0..iterations {
+        unsafe { post.write(0) };
+    }
+}
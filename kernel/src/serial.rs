@@ -10,6 +10,24 @@ lazy_static! {
     };
 }
 
+/// Waits until everything written to `SERIAL1` has left the UART, so a reset or power off doesn't
+/// cut the last lines off.
+pub fn flush() {
+    use x86_64::instructions::{interrupts, port::Port};
+    // line status register, bit 6 is set once the transmitter is completely empty
+    let mut line_status = Port::<u8>::new(0x3F8 + 5);
+    interrupts::without_interrupts(|| {
+        let _serial = SERIAL1.lock(); // nobody else gets to write in the meantime
+        // bounded, a missing UART shouldn't hang the shutdown
+        for _ in 0..1_000_000 {
+            if unsafe { line_status.read() } & 0x40 != 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    });
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
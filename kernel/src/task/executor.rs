@@ -60,6 +60,7 @@ impl Executor {
     pub fn run(&mut self) -> ! {
         loop {
             self.run_ready_tasks();
+            crate::interrupts::exceptions::report_nmis();
             self.sleep_if_idle();
         }
     }
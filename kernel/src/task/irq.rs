@@ -0,0 +1,169 @@
+//! Futures that interrupt handlers wake. An `IrqEvent` is a flag the handler raises and a task
+//! awaits, an `IrqQueue` carries data (scancodes, received bytes...) to a task as a `Stream`.
+//!
+//! Both only keep one waker, so there's one task waiting on each. Signalling and pushing never
+//! block or allocate, so they're fine to call from a handler.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::{Stream, task::AtomicWaker};
+
+/// Something an interrupt handler signals and a task waits for.
+///
+/// Signals that come in while nobody is waiting aren't lost, the next `wait` finishes straight
+/// away and says how many there were.
+pub struct IrqEvent {
+    pending: AtomicU64,
+    waker: AtomicWaker,
+}
+
+impl IrqEvent {
+    pub const fn new() -> Self {
+        IrqEvent {
+            pending: AtomicU64::new(0),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Called by the interrupt handler
+    pub fn signal(&self) {
+        self.pending.fetch_add(1, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Waits for the next signal, resolving to the number of signals since the last wait.
+    pub fn wait(&self) -> Wait<'_> {
+        Wait { event: self }
+    }
+
+    /// Takes the signals that came in without waiting for one.
+    pub fn take(&self) -> u64 {
+        self.pending.swap(0, Ordering::Acquire)
+    }
+}
+
+impl Default for IrqEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[must_use = "futures do nothing unless awaited"]
+pub struct Wait<'a> {
+    event: &'a IrqEvent,
+}
+
+impl Future for Wait<'_> {
+    type Output = u64;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u64> {
+        let event = self.event;
+        match event.take() {
+            0 => {}
+            signals => return Poll::Ready(signals),
+        }
+
+        // register first, so a signal between the check and here still wakes us
+        event.waker.register(cx.waker());
+        match event.take() {
+            0 => Poll::Pending,
+            signals => {
+                event.waker.take();
+                Poll::Ready(signals)
+            }
+        }
+    }
+}
+
+/// A bounded queue an interrupt handler pushes into and a task reads as a `Stream`.
+///
+/// The queue itself is allocated by `stream`, so it can be a `static` the handler reaches.
+/// Anything pushed before that, or while the queue is full, is dropped.
+pub struct IrqQueue<T> {
+    capacity: usize,
+    queue: OnceCell<ArrayQueue<T>>,
+    waker: AtomicWaker,
+    dropped: AtomicU64,
+}
+
+impl<T> IrqQueue<T> {
+    pub const fn new(capacity: usize) -> Self {
+        IrqQueue {
+            capacity,
+            queue: OnceCell::uninit(),
+            waker: AtomicWaker::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Called by the interrupt handler
+    ///
+    /// Must not block or allocate. Gives `value` back if there's nowhere to put it.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let Ok(queue) = self.queue.try_get() else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(value);
+        };
+        match queue.push(value) {
+            Ok(()) => {
+                self.waker.wake();
+                Ok(())
+            }
+            Err(value) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Err(value)
+            }
+        }
+    }
+
+    /// Values thrown away because the queue was full or there was no stream yet.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Allocates the queue and returns its only consumer.
+    ///
+    /// Panics if called twice.
+    pub fn stream(&'static self) -> IrqStream<T> {
+        self.queue
+            .try_init_once(|| ArrayQueue::new(self.capacity))
+            .expect("IrqQueue::stream should only be called once");
+        IrqStream { queue: self }
+    }
+}
+
+pub struct IrqStream<T: 'static> {
+    queue: &'static IrqQueue<T>,
+}
+
+impl<T> IrqStream<T> {
+    /// Takes the next value if there is one, without waiting.
+    pub fn try_next(&mut self) -> Option<T> {
+        self.queue.queue.try_get().ok()?.pop()
+    }
+}
+
+impl<T> Stream for IrqStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let queue = self.queue.queue.try_get().expect("not initialized");
+        if let Some(value) = queue.pop() {
+            return Poll::Ready(Some(value));
+        }
+
+        self.queue.waker.register(cx.waker());
+        match queue.pop() {
+            Some(value) => {
+                self.queue.waker.take();
+                Poll::Ready(Some(value))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
@@ -1,47 +1,30 @@
-use core::task::Poll;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 
-use conquer_once::spin::OnceCell;
-use crossbeam_queue::ArrayQueue;
-use futures_util::{task::AtomicWaker, Stream, StreamExt};
+use futures_util::{Stream, StreamExt};
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 
-static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+use super::irq::{IrqQueue, IrqStream};
 
-static WAKER: AtomicWaker = AtomicWaker::new();
+static SCANCODES: IrqQueue<u8> = IrqQueue::new(100);
 
 pub struct ScancodeStream {
-    _private: (),
+    scancodes: IrqStream<u8>,
 }
 
 impl ScancodeStream {
     pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new should only be called once");
-        ScancodeStream { _private: () }
+        ScancodeStream {
+            scancodes: SCANCODES.stream(),
+        }
     }
 }
 
 impl Stream for ScancodeStream {
     type Item = u8;
 
-    fn poll_next(
-        self: core::pin::Pin<&mut Self>,
-        cx: &mut core::task::Context<'_>,
-    ) -> core::task::Poll<Option<Self::Item>> {
-        let queue = SCANCODE_QUEUE.try_get().expect("not initialized");
-        if let Some(scancode) = queue.pop() {
-            return Poll::Ready(Some(scancode));
-        }
-
-        WAKER.register(&cx.waker());
-        match queue.pop() {
-            Some(scancode) => {
-                WAKER.take();
-                Poll::Ready(Some(scancode))
-            }
-            None => Poll::Pending,
-        }
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.scancodes).poll_next(cx)
     }
 }
 
@@ -49,14 +32,8 @@ impl Stream for ScancodeStream {
 ///
 /// Must not block or allocate.
 pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if let Err(_) = queue.push(scancode) {
-            log::warn!("scancode queue full! dropping keyboard input!");
-        } else {
-            WAKER.wake();
-        }
-    } else {
-        log::warn!("scancode queue uninitalized!");
+    if SCANCODES.push(scancode).is_err() {
+        log::warn!("scancode queue full or uninitialized! dropping keyboard input!");
     }
 }
 
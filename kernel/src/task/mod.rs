@@ -3,7 +3,9 @@ use core::{future::Future, pin::Pin, sync::atomic::{AtomicU64, Ordering}, task::
 
 pub mod simple_executor;
 pub mod executor;
+pub mod irq;
 pub mod keyboard;
+pub mod timer;
 
 pub struct Task {
     id: TaskId,
@@ -0,0 +1,258 @@
+//! Futures that wait for time: `sleep`, `sleep_until`, `interval` and `timeout`.
+//!
+//! Pending timers sit in a min-heap ordered by deadline, which the timer interrupt walks to wake
+//! the expired ones (like `IrqQueue::push` wakes its stream). So the resolution is one
+//! tick, `time::TICK_HZ`.
+
+use alloc::collections::BinaryHeap;
+use core::cmp::{Ordering, Reverse};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{self, AtomicU64};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use futures_util::Stream;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::time::Instant;
+
+/// Only locked with interrupts off, so the timer interrupt never finds it held on this CPU.
+static TIMERS: Mutex<BinaryHeap<Reverse<TimerEntry>>> = Mutex::new(BinaryHeap::new());
+
+struct TimerEntry {
+    deadline: Instant,
+    id: u64,
+    waker: Waker,
+}
+
+// ordered by deadline, then by registration so equal deadlines fire in order
+impl Ord for TimerEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deadline, self.id).cmp(&(other.deadline, other.id))
+    }
+}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TimerEntry {}
+
+fn next_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed)
+}
+
+/// Called by the timer interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn wake_expired() {
+    let Some(mut timers) = TIMERS.try_lock() else {
+        return; // can't happen on one CPU, see `TIMERS`
+    };
+    let now = Instant::now();
+    while let Some(Reverse(entry)) = timers.peek()
+        && entry.deadline <= now
+    {
+        let Reverse(entry) = timers.pop().unwrap();
+        entry.waker.wake();
+    }
+}
+
+/// Timers waiting to fire.
+pub fn pending() -> usize {
+    interrupts::without_interrupts(|| TIMERS.lock().len())
+}
+
+fn cancel(id: u64) {
+    interrupts::without_interrupts(|| {
+        TIMERS.lock().retain(|Reverse(entry)| entry.id != id);
+    });
+}
+
+/// `duration` from now. Huge durations (`Duration::MAX` for "no timeout") become `Instant::MAX`
+/// rather than overflowing.
+fn after(duration: Duration) -> Instant {
+    Instant::now().checked_add(duration).unwrap_or(Instant::MAX)
+}
+
+/// A future that completes at `deadline`, see `sleep` and `sleep_until`.
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    /// Set while there's an entry in `TIMERS` for this.
+    id: Option<u64>,
+}
+
+/// Waits for `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(after(duration))
+}
+
+/// Waits until `deadline`, which is already done if it has passed.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, id: None }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Moves the deadline, the sleep can be awaited again after this.
+    pub fn reset(&mut self, deadline: Instant) {
+        self.deadline = deadline;
+        if let Some(id) = self.id.take() {
+            cancel(id);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_elapsed() {
+            if let Some(id) = self.id.take() {
+                cancel(id);
+            }
+            return Poll::Ready(());
+        }
+
+        let id = *self.id.get_or_insert_with(next_id);
+        let deadline = self.deadline;
+        interrupts::without_interrupts(|| {
+            let mut timers = TIMERS.lock();
+            // the task may have moved to another waker since the last poll
+            timers.retain(|Reverse(entry)| entry.id != id);
+            timers.push(Reverse(TimerEntry {
+                deadline,
+                id,
+                waker: cx.waker().clone(),
+            }));
+        });
+
+        // the deadline may have passed while registering, and the interrupt already went by
+        if self.is_elapsed() {
+            cancel(id);
+            self.id = None;
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            cancel(id);
+        }
+    }
+}
+
+/// Yields every `period`, see `interval`.
+#[derive(Debug)]
+pub struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+/// Ticks right away, then every `period`. If the task falls behind by more than a period the
+/// missed ticks are skipped rather than fired in a burst.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval {
+        period,
+        sleep: sleep_until(Instant::now()),
+    }
+}
+
+impl Interval {
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Waits for the next tick, returning when it was due.
+    pub async fn tick(&mut self) -> Instant {
+        core::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let due = self.sleep.deadline();
+        let mut next = due.checked_add(self.period).unwrap_or(Instant::MAX);
+        let now = Instant::now();
+        if next <= now {
+            next = now.checked_add(self.period).unwrap_or(Instant::MAX);
+        }
+        self.sleep.reset(next);
+        Poll::Ready(due)
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        self.poll_tick(cx).map(Some)
+    }
+}
+
+/// The error `timeout` gives when the time ran out first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl core::fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+/// Runs `future`, giving up after `duration`. See `timeout`.
+#[derive(Debug)]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+/// Runs `future` for at most `duration`, dropping it if it takes longer.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    timeout_at(after(duration), future)
+}
+
+/// Runs `future` until `deadline`, dropping it if it takes longer.
+pub fn timeout_at<F: Future>(deadline: Instant, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep_until(deadline),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // `future` stays pinned, `sleep` is Unpin and never handed out pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|()| Err(Elapsed))
+    }
+}
@@ -0,0 +1,234 @@
+//! Kernel time: a periodic tick from the PIT or HPET, a monotonic clock built on the TSC and the
+//! wall clock from the RTC.
+//!
+//! `init` needs the heap, `acpi::init` and `apic::init` (for the HPET). Before it runs the clock falls back
+//! to counting ticks at whatever rate the PIT was left at, so it's monotonic but not accurate.
+
+use conquer_once::spin::OnceCell;
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+pub mod system_time;
+pub mod tsc;
+
+use crate::acpi::madt::{Polarity, TriggerMode};
+use crate::interrupts::{InterruptIndex, apic, irq};
+use hpet::Hpet;
+pub use system_time::{SystemTime, SystemTimeError, UNIX_EPOCH};
+
+/// How often the timer interrupt fires.
+pub const TICK_HZ: u32 = 1000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// `Instant::now` never goes below this, so switching clocks can't make time go backwards.
+static LAST: AtomicU64 = AtomicU64::new(0);
+/// Where the clock was when the TSC took over, its nanoseconds count on from here.
+static TSC_OFFSET: AtomicU64 = AtomicU64::new(0);
+static HPET: OnceCell<Hpet> = OnceCell::uninit();
+static TICK_SOURCE: OnceCell<TickSource> = OnceCell::uninit();
+
+/// What drives the timer interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickSource {
+    Pit,
+    Hpet,
+}
+
+/// Calibrates the TSC, starts the tick (on the HPET if ACPI reports one and the APICs are up)
+/// and sets the wall clock from the RTC.
+pub fn init() {
+    if let Ok(info) = crate::acpi::hpet::Hpet::get() {
+        match unsafe { Hpet::new(&info) } {
+            Ok(hpet) => _ = HPET.try_init_once(|| hpet),
+            Err(err) => log::warn!("time: not using the HPET, {err}"),
+        }
+    }
+
+    let offset = Instant::now().nanos;
+    let tsc_hz = match HPET.get() {
+        Some(hpet) => tsc::calibrate(|nanos| hpet.wait(nanos)),
+        None => tsc::calibrate(pit::wait),
+    };
+    TSC_OFFSET.store(offset, Ordering::Release);
+
+    let source = match HPET.get() {
+        Some(hpet) if start_hpet_tick(hpet) => TickSource::Hpet,
+        _ => {
+            pit::set_frequency(TICK_HZ);
+            TickSource::Pit
+        }
+    };
+    TICK_SOURCE.init_once(|| source);
+
+    log::info!(
+        "time: TSC at {} MHz ({}), {:?} ticking at {} Hz",
+        tsc_hz / 1_000_000,
+        if tsc::is_invariant() {
+            "invariant"
+        } else {
+            "not invariant"
+        },
+        source,
+        TICK_HZ
+    );
+    rtc::init();
+    log::info!("time: wall clock at {}", system_time::sync());
+}
+
+/// Ticks with HPET timer 0, wired to the first I/O APIC input past the ISA IRQs it can reach.
+/// Without the APICs only the PIT's IRQ 0 gets through.
+fn start_hpet_tick(hpet: &Hpet) -> bool {
+    if !apic::is_enabled() {
+        return false;
+    }
+    let routable = hpet.routable_gsis();
+    let Some(gsi) = (16..32).find(|&gsi| routable & 1 << gsi != 0 && apic::has_gsi(gsi)) else {
+        return false;
+    };
+    if !hpet.start_periodic(TICK_HZ, gsi) {
+        return false;
+    }
+    let irq = InterruptIndex::Timer.irq();
+    apic::route_gsi(
+        gsi,
+        irq::vector(irq),
+        Polarity::ActiveHigh,
+        TriggerMode::Edge,
+    );
+    // the PIT keeps counting, it just can't get through anymore
+    apic::set_isa_irq_masked(irq, true);
+    true
+}
+
+/// Called by the timer interrupt.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// What's driving the timer interrupt, `None` before `init`.
+pub fn tick_source() -> Option<TickSource> {
+    TICK_SOURCE.get().copied()
+}
+
+/// Time since boot (more or less, the clock starts counting with the first tick).
+pub fn uptime() -> Duration {
+    Instant::now().duration_since(Instant::ZERO)
+}
+
+/// A point on the monotonic clock, like `std::time::Instant`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    /// The clock's starting point.
+    pub const ZERO: Instant = Instant { nanos: 0 };
+    /// Centuries of uptime away, for deadlines that should never come.
+    pub const MAX: Instant = Instant { nanos: u64::MAX };
+
+    pub fn now() -> Instant {
+        let nanos = match tsc::nanos() {
+            Some(nanos) => TSC_OFFSET.load(Ordering::Acquire) + nanos,
+            None => ticks() * 1_000_000_000 / u64::from(TICK_HZ),
+        };
+        let last = LAST.fetch_max(nanos, Ordering::Relaxed);
+        Instant {
+            nanos: nanos.max(last),
+        }
+    }
+
+    /// Nanoseconds since `Instant::ZERO`.
+    pub fn as_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    /// Time since `earlier`, zero if `earlier` is actually later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.nanos
+            .checked_sub(earlier.nanos)
+            .map(Duration::from_nanos)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Instant {
+            nanos: self.nanos.checked_add(nanos)?,
+        })
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Instant {
+            nanos: self.nanos.checked_sub(nanos)?,
+        })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Prints as seconds since boot, like `12.345678`.
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:06}",
+            self.nanos / 1_000_000_000,
+            self.nanos % 1_000_000_000 / 1000
+        )
+    }
+}
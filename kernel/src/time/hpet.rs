@@ -0,0 +1,140 @@
+use core::fmt;
+
+use crate::acpi;
+use crate::memory::mmio::{self, MmioError, MmioRegion};
+
+const CAPABILITIES: usize = 0x00;
+const CONFIG: usize = 0x10;
+const MAIN_COUNTER: usize = 0xf0;
+const TIMER_0_CONFIG: usize = 0x100;
+const TIMER_0_COMPARATOR: usize = 0x108;
+
+// CAPABILITIES bits
+const COUNTER_64BIT: u64 = 1 << 13;
+// CONFIG bits
+const ENABLE: u64 = 1 << 0;
+const LEGACY_ROUTE: u64 = 1 << 1;
+// timer config bits
+const LEVEL_TRIGGERED: u64 = 1 << 1;
+const INTERRUPT_ENABLE: u64 = 1 << 2;
+const PERIODIC: u64 = 1 << 3;
+const PERIODIC_CAPABLE: u64 = 1 << 4;
+const VALUE_SET: u64 = 1 << 6;
+const INTERRUPT_ROUTE_SHIFT: u32 = 9;
+const INTERRUPT_ROUTE_MASK: u64 = 0x1f << INTERRUPT_ROUTE_SHIFT;
+
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+/// The spec caps the tick at 100 ns, anything slower (or zero) is a broken HPET.
+const MAX_PERIOD_FEMTOS: u64 = 0x05f5_e100;
+
+#[derive(Debug)]
+pub enum HpetError {
+    Mmio(MmioError),
+    /// The capabilities register reports a tick length outside 1 fs..=100 ns.
+    BadPeriod(u64),
+    /// The main counter is only 32 bits, it would wrap every few minutes.
+    Counter32Bit,
+}
+
+impl fmt::Display for HpetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HpetError::Mmio(err) => write!(f, "can't map it: {err}"),
+            HpetError::BadPeriod(femtos) => write!(f, "bogus counter period of {femtos} fs"),
+            HpetError::Counter32Bit => write!(f, "its counter is only 32 bits"),
+        }
+    }
+}
+
+/// The HPET's first timer block: a free running main counter and its comparators.
+#[derive(Debug)]
+pub struct Hpet {
+    regs: MmioRegion,
+    /// Length of one counter tick.
+    period_femtos: u64,
+}
+
+impl Hpet {
+    /// Maps the HPET the ACPI table describes and starts its main counter.
+    ///
+    /// This function is unsafe because the caller must make sure `info` comes from the ACPI
+    /// tables and nothing else is using the HPET.
+    pub unsafe fn new(info: &acpi::hpet::Hpet) -> Result<Self, HpetError> {
+        let regs = unsafe { mmio::map_mmio(info.base_address, 0x400) }.map_err(HpetError::Mmio)?;
+        let capabilities = regs.read::<u64>(CAPABILITIES);
+        let period_femtos = capabilities >> 32;
+        if !(1..=MAX_PERIOD_FEMTOS).contains(&period_femtos) {
+            return Err(HpetError::BadPeriod(period_femtos));
+        }
+        if capabilities & COUNTER_64BIT == 0 {
+            return Err(HpetError::Counter32Bit);
+        }
+        let hpet = Hpet {
+            regs,
+            period_femtos,
+        };
+        let config = hpet.regs.read::<u64>(CONFIG);
+        hpet.regs.write(CONFIG, config | ENABLE);
+        Ok(hpet)
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.regs.read(MAIN_COUNTER)
+    }
+
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period_femtos
+    }
+
+    /// Converts counter ticks to nanoseconds.
+    pub fn nanos(&self, ticks: u64) -> u64 {
+        (u128::from(ticks) * u128::from(self.period_femtos) / u128::from(FEMTOS_PER_NANO)) as u64
+    }
+
+    /// Busy waits for at least `nanos`, returning how long it actually took.
+    pub fn wait(&self, nanos: u64) -> u64 {
+        let start = self.counter();
+        loop {
+            let elapsed = self.nanos(self.counter().wrapping_sub(start));
+            if elapsed >= nanos {
+                return elapsed;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// The I/O APIC inputs timer 0 can be wired to, one bit per GSI.
+    pub fn routable_gsis(&self) -> u32 {
+        (self.regs.read::<u64>(TIMER_0_CONFIG) >> 32) as u32
+    }
+
+    /// Makes timer 0 fire `hz` times a second as an edge on I/O APIC input `gsi`, which has to be
+    /// one of `routable_gsis`. `false` if this HPET can't do that.
+    ///
+    /// Legacy replacement routing stays off, it would take IRQ 8 from the RTC as well as IRQ 0.
+    pub fn start_periodic(&self, hz: u32, gsi: u32) -> bool {
+        let timer = self.regs.read::<u64>(TIMER_0_CONFIG);
+        if timer & PERIODIC_CAPABLE == 0 || gsi >= 32 || self.routable_gsis() & 1 << gsi == 0 {
+            return false;
+        }
+        let period = self.frequency() / u64::from(hz);
+
+        // the counter has to be stopped to set up a periodic timer
+        let config = self.regs.read::<u64>(CONFIG) & !(ENABLE | LEGACY_ROUTE);
+        self.regs.write(CONFIG, config);
+        let timer = timer & !(INTERRUPT_ROUTE_MASK | LEVEL_TRIGGERED);
+        self.regs.write(
+            TIMER_0_CONFIG,
+            timer
+                | u64::from(gsi) << INTERRUPT_ROUTE_SHIFT
+                | INTERRUPT_ENABLE
+                | PERIODIC
+                | VALUE_SET,
+        );
+        // with VALUE_SET the first write is the next deadline, the second the period
+        self.regs.write(TIMER_0_COMPARATOR, self.counter() + period);
+        self.regs.write(TIMER_0_COMPARATOR, period);
+        self.regs.write(CONFIG, config | ENABLE);
+        true
+    }
+}
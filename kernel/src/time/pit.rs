@@ -0,0 +1,55 @@
+use x86_64::instructions::port::Port;
+
+/// The PIT's input clock.
+pub const FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Port B of the old keyboard controller, gates channel 2 and shows its output.
+const PORT_B: u16 = 0x61;
+
+const GATE_2: u8 = 1 << 0;
+const SPEAKER: u8 = 1 << 1;
+const OUT_2: u8 = 1 << 5;
+
+// channel (bits 6-7), lo/hi byte access (4-5), mode (1-3)
+const CHANNEL_0_RATE_GENERATOR: u8 = 0x34;
+const CHANNEL_2_ONE_SHOT: u8 = 0xb0;
+
+/// Makes channel 0 (IRQ 0) fire `hz` times a second. Returns the rate it actually runs at, which
+/// is off a bit since the divisor has to be a whole number.
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = (FREQUENCY / hz).clamp(1, 0xffff);
+    unsafe {
+        Port::<u8>::new(COMMAND).write(CHANNEL_0_RATE_GENERATOR);
+        let mut data = Port::<u8>::new(CHANNEL_0);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    }
+    FREQUENCY / divisor
+}
+
+/// Busy waits on channel 2 for `nanos` (at most ~54 ms), without touching channel 0 or needing
+/// interrupts. Returns how long it really waited, after rounding to whole PIT ticks.
+pub fn wait(nanos: u64) -> u64 {
+    let count = (nanos * u64::from(FREQUENCY) / 1_000_000_000).clamp(1, 0xffff);
+    unsafe {
+        let mut port_b = Port::<u8>::new(PORT_B);
+        // gate low while programming, and keep the speaker quiet
+        let gate = port_b.read() & !(GATE_2 | SPEAKER);
+        port_b.write(gate);
+
+        Port::<u8>::new(COMMAND).write(CHANNEL_2_ONE_SHOT);
+        let mut data = Port::<u8>::new(CHANNEL_2);
+        data.write(count as u8);
+        data.write((count >> 8) as u8);
+
+        port_b.write(gate | GATE_2);
+        while port_b.read() & OUT_2 == 0 {
+            core::hint::spin_loop();
+        }
+        port_b.write(gate);
+    }
+    count * 1_000_000_000 / u64::from(FREQUENCY)
+}
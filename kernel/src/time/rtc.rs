@@ -0,0 +1,301 @@
+//! The CMOS real-time clock: the date and time the firmware keeps, to the second.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::interrupts::{InterruptIndex, PICS, apic};
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+const STATUS_C: u8 = 0x0c;
+/// Where QEMU, and most PCs, keep the century when the FADT doesn't say.
+const DEFAULT_CENTURY: u8 = 0x32;
+
+// STATUS_A bits
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const RATE_MASK: u8 = 0x0f;
+// STATUS_B bits
+const HOURS_24: u8 = 1 << 1;
+const BINARY: u8 = 1 << 2;
+const UPDATE_ENDED_INTERRUPT: u8 = 1 << 4;
+const PERIODIC_INTERRUPT: u8 = 1 << 6;
+// STATUS_C bits
+const UPDATE_ENDED_FLAG: u8 = 1 << 4;
+const PERIODIC_FLAG: u8 = 1 << 6;
+/// Set in the hour in 12 hour mode.
+const PM: u8 = 1 << 7;
+
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(DEFAULT_CENTURY);
+static UPDATE_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+static PERIODIC_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+/// A UTC date and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01T00:00:00Z.
+    pub fn to_unix_seconds(&self) -> u64 {
+        let days = days_from_civil(i64::from(self.year), self.month.into(), self.day.into());
+        let seconds = days * 86400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+        seconds.max(0) as u64
+    }
+
+    pub fn from_unix_seconds(seconds: u64) -> DateTime {
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+        let time = seconds % 86400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+}
+
+/// ISO 8601, like `2024-02-29T12:00:00Z`.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// Howard Hinnant's algorithms, counting in 400 year eras that start on March 1st so the leap
+// day comes last
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Picks up the century register from the FADT, if it has one.
+pub fn init() {
+    if let Ok(fadt) = crate::acpi::fadt::Fadt::get()
+        && fadt.century != 0
+    {
+        CENTURY_REGISTER.store(fadt.century, Ordering::Relaxed);
+    }
+}
+
+fn read_register(register: u8) -> u8 {
+    // bit 7 of the index port masks NMIs, which we leave alone
+    unsafe {
+        Port::<u8>::new(CMOS_INDEX).write(register & 0x7f);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+fn write_register(register: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(CMOS_INDEX).write(register & 0x7f);
+        Port::<u8>::new(CMOS_DATA).write(value);
+    }
+}
+
+/// The raw registers, read outside of an update.
+#[derive(PartialEq, Eq)]
+struct Registers {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+fn read_registers() -> Registers {
+    while read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    Registers {
+        second: read_register(SECONDS),
+        minute: read_register(MINUTES),
+        hour: read_register(HOURS),
+        day: read_register(DAY),
+        month: read_register(MONTH),
+        year: read_register(YEAR),
+        century: read_register(CENTURY_REGISTER.load(Ordering::Relaxed)),
+    }
+}
+
+/// Reads the current date and time. Takes up to ~2 ms if the RTC is in the middle of an update.
+pub fn read() -> DateTime {
+    let (registers, status_b) = interrupts::without_interrupts(|| {
+        // an update can still start between the check and the reads, so read until two agree
+        let mut registers = read_registers();
+        loop {
+            let again = read_registers();
+            if again == registers {
+                break;
+            }
+            registers = again;
+        }
+        (registers, read_register(STATUS_B))
+    });
+
+    let decode = |value: u8| {
+        if status_b & BINARY != 0 {
+            value
+        } else {
+            (value >> 4) * 10 + (value & 0x0f)
+        }
+    };
+    let mut hour = decode(registers.hour & !PM);
+    if status_b & HOURS_24 == 0 {
+        // 12 AM is midnight, 12 PM is noon
+        hour %= 12;
+        if registers.hour & PM != 0 {
+            hour += 12;
+        }
+    }
+    let century = match decode(registers.century) {
+        century @ 19..=99 => u16::from(century),
+        _ => 20, // no (usable) century register
+    };
+
+    DateTime {
+        year: century * 100 + u16::from(decode(registers.year)),
+        month: decode(registers.month),
+        day: decode(registers.day),
+        hour,
+        minute: decode(registers.minute),
+        second: decode(registers.second),
+    }
+}
+
+/// Interrupts the RTC can raise on IRQ 8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcInterrupt {
+    /// Once a second, right after the time changed.
+    Update,
+    /// At `32768 >> (rate - 1)` Hz, `rate` from 3 (8 kHz) to 15 (2 Hz).
+    Periodic { rate: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcError {
+    InvalidRate(u8),
+}
+
+impl fmt::Display for RtcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RtcError::InvalidRate(rate) => write!(f, "invalid periodic rate {rate}"),
+        }
+    }
+}
+
+/// Turns on `interrupt` and unmasks IRQ 8.
+pub fn enable_interrupt(interrupt: RtcInterrupt) -> Result<(), RtcError> {
+    interrupts::without_interrupts(|| {
+        let status_b = read_register(STATUS_B);
+        match interrupt {
+            RtcInterrupt::Update => write_register(STATUS_B, status_b | UPDATE_ENDED_INTERRUPT),
+            RtcInterrupt::Periodic { rate } => {
+                if !(3..=15).contains(&rate) {
+                    return Err(RtcError::InvalidRate(rate));
+                }
+                let status_a = read_register(STATUS_A);
+                write_register(STATUS_A, status_a & !RATE_MASK | rate);
+                write_register(STATUS_B, status_b | PERIODIC_INTERRUPT);
+            }
+        }
+        // an interrupt that's already flagged would keep IRQ 8 from ever firing
+        read_register(STATUS_C);
+        Ok(())
+    })?;
+
+    let irq = InterruptIndex::Rtc.irq();
+    if apic::is_enabled() {
+        apic::set_isa_irq_masked(irq, false);
+    } else {
+        unsafe {
+            let mut pics = PICS.lock();
+            let [master, slave] = pics.read_masks();
+            // IRQ 8 is the slave's first line, which comes in over the master's IRQ 2
+            pics.write_masks(master & !(1 << 2), slave & !(1 << (irq - 8)));
+        }
+    }
+    Ok(())
+}
+
+/// Turns off `interrupt`.
+pub fn disable_interrupt(interrupt: RtcInterrupt) {
+    interrupts::without_interrupts(|| {
+        let bit = match interrupt {
+            RtcInterrupt::Update => UPDATE_ENDED_INTERRUPT,
+            RtcInterrupt::Periodic { .. } => PERIODIC_INTERRUPT,
+        };
+        write_register(STATUS_B, read_register(STATUS_B) & !bit);
+    });
+}
+
+/// Update interrupts so far.
+pub fn update_interrupts() -> u64 {
+    UPDATE_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+/// Periodic interrupts so far.
+pub fn periodic_interrupts() -> u64 {
+    PERIODIC_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+/// Called by the RTC interrupt handler
+pub(crate) fn handle_interrupt() {
+    // reading status C acknowledges the interrupt, the RTC won't raise another one before that
+    let flags = read_register(STATUS_C);
+    if flags & UPDATE_ENDED_FLAG != 0 {
+        UPDATE_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    }
+    if flags & PERIODIC_FLAG != 0 {
+        PERIODIC_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
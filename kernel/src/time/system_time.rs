@@ -0,0 +1,143 @@
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use super::{Instant, rtc::DateTime};
+
+/// Nanoseconds since the Unix epoch at `Instant::ZERO`, set by `sync`.
+static BOOT_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Wall clock time, like `std::time::SystemTime`: the RTC's date at boot plus the monotonic
+/// clock, so it only has the RTC's one second accuracy but never jumps on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime {
+    /// Since `UNIX_EPOCH`.
+    nanos: u64,
+}
+
+pub const UNIX_EPOCH: SystemTime = SystemTime { nanos: 0 };
+
+/// `earlier` was actually later, by `duration()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SystemTimeError(Duration);
+
+impl SystemTimeError {
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl fmt::Display for SystemTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "second time provided was later than self")
+    }
+}
+
+/// Sets the wall clock from the RTC.
+pub fn sync() -> DateTime {
+    let date_time = super::rtc::read();
+    let nanos = date_time.to_unix_seconds() * 1_000_000_000;
+    BOOT_EPOCH.store(
+        nanos.saturating_sub(Instant::now().as_nanos()),
+        Ordering::Relaxed,
+    );
+    date_time
+}
+
+impl SystemTime {
+    /// The current time, which starts at `UNIX_EPOCH` until `sync` ran.
+    pub fn now() -> SystemTime {
+        SystemTime {
+            nanos: BOOT_EPOCH.load(Ordering::Relaxed) + Instant::now().as_nanos(),
+        }
+    }
+
+    pub fn duration_since(&self, earlier: SystemTime) -> Result<Duration, SystemTimeError> {
+        match self.nanos.checked_sub(earlier.nanos) {
+            Some(nanos) => Ok(Duration::from_nanos(nanos)),
+            None => Err(SystemTimeError(Duration::from_nanos(
+                earlier.nanos - self.nanos,
+            ))),
+        }
+    }
+
+    pub fn elapsed(&self) -> Result<Duration, SystemTimeError> {
+        SystemTime::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<SystemTime> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(SystemTime {
+            nanos: self.nanos.checked_add(nanos)?,
+        })
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<SystemTime> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(SystemTime {
+            nanos: self.nanos.checked_sub(nanos)?,
+        })
+    }
+
+    /// The calendar date and time, to the second.
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_unix_seconds(self.nanos / 1_000_000_000)
+    }
+}
+
+impl From<DateTime> for SystemTime {
+    fn from(date_time: DateTime) -> SystemTime {
+        SystemTime {
+            nanos: date_time.to_unix_seconds() * 1_000_000_000,
+        }
+    }
+}
+
+impl Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, duration: Duration) -> SystemTime {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to system time")
+    }
+}
+
+impl AddAssign<Duration> for SystemTime {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn sub(self, duration: Duration) -> SystemTime {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from system time")
+    }
+}
+
+impl SubAssign<Duration> for SystemTime {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+/// ISO 8601 with milliseconds, like `2024-02-29T12:00:00.123Z`.
+impl fmt::Display for SystemTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date_time = self.date_time();
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            date_time.year,
+            date_time.month,
+            date_time.day,
+            date_time.hour,
+            date_time.minute,
+            date_time.second,
+            self.nanos % 1_000_000_000 / 1_000_000
+        )
+    }
+}
@@ -0,0 +1,53 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// How long calibration measures for, long enough for a decent reading without slowing the boot.
+const CALIBRATION_NANOS: u64 = 10_000_000;
+
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// The TSC value that `nanos` counts from.
+static START: AtomicU64 = AtomicU64::new(0);
+
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Whether the TSC ticks at a constant rate, no matter the power state or frequency scaling.
+pub fn is_invariant() -> bool {
+    let max_extended = unsafe { __cpuid(0x8000_0000) }.eax;
+    max_extended >= 0x8000_0007 && unsafe { __cpuid(0x8000_0007) }.edx & (1 << 8) != 0
+}
+
+/// Ticks per second, once `calibrate` ran.
+pub fn frequency() -> Option<u64> {
+    match FREQUENCY.load(Ordering::Acquire) {
+        0 => None,
+        hz => Some(hz),
+    }
+}
+
+/// Nanoseconds since calibration.
+pub fn nanos() -> Option<u64> {
+    let hz = frequency()?;
+    let ticks = read().wrapping_sub(START.load(Ordering::Relaxed));
+    Some((u128::from(ticks) * 1_000_000_000 / u128::from(hz)) as u64)
+}
+
+/// Measures the TSC against `wait`, which busy waits for about the given nanoseconds on a clock
+/// with a known rate and returns how long it really waited.
+pub(super) fn calibrate(wait: impl Fn(u64) -> u64) -> u64 {
+    // best of a few tries, in case something (an SMI, the hypervisor) got in the way
+    let hz = (0..3)
+        .map(|_| {
+            let start = read();
+            let nanos = wait(CALIBRATION_NANOS);
+            let ticks = read() - start;
+            u128::from(ticks) * 1_000_000_000 / u128::from(nanos)
+        })
+        .min()
+        .unwrap() as u64;
+
+    START.store(read(), Ordering::Relaxed);
+    FREQUENCY.store(hz, Ordering::Release);
+    hz
+}
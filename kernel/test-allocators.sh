@@ -0,0 +1,9 @@
+#!/bin/sh
+# Runs the heap tests once for every global allocator the kernel can be built with.
+set -e
+cd "$(dirname "$0")"
+
+for allocator in alloc-bump alloc-linked-list alloc-fixed-block alloc-slab; do
+    echo "heap_allocation with $allocator"
+    cargo test --no-default-features --features "$allocator" --test heap_allocation "$@"
+done
@@ -0,0 +1,9 @@
+#!/bin/sh
+# Runs the ACPI tests on both of QEMU's PC machines, extra arguments go to QEMU.
+set -e
+cd "$(dirname "$0")"
+
+for machine in q35 pc; do
+    echo "acpi on $machine"
+    cargo test --test acpi -- -machine "$machine" "$@"
+done
@@ -0,0 +1,125 @@
+//! Checks the tables QEMU builds. They differ between machines, `test-machines.sh` runs this
+//! under both `q35` and `pc`; which one we're on is told apart by the MCFG, only q35 has one.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader_api::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use kernel::acpi::{
+    self, AcpiError, AddressSpace,
+    fadt::Fadt,
+    hpet::Hpet,
+    madt::{Madt, Polarity, TriggerMode},
+    mcfg::Mcfg,
+};
+use kernel::memory;
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+    unsafe { acpi::init(boot_info.rsdp_addr.into_option()) }.expect("ACPI init failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn is_q35() -> bool {
+    match Mcfg::get() {
+        Ok(_) => true,
+        Err(AcpiError::TableNotFound(_)) => false,
+        Err(err) => panic!("{err}"),
+    }
+}
+
+#[test_case]
+fn every_table_has_a_header() {
+    let mut count = 0;
+    for address in acpi::tables().unwrap() {
+        let header = acpi::header_at(address);
+        assert!(header.signature.iter().all(u8::is_ascii_uppercase));
+        assert!(header.length as usize >= acpi::SDT_HEADER_SIZE);
+        count += 1;
+    }
+    assert!(count >= 3);
+    assert_eq!(
+        acpi::find_table(b"NOPE"),
+        Err(AcpiError::TableNotFound(*b"NOPE"))
+    );
+}
+
+#[test_case]
+fn madt_has_the_boot_cpu_and_an_io_apic() {
+    let madt = Madt::get().unwrap();
+    assert_eq!(madt.local_apic_address, PhysAddr::new(0xfee0_0000));
+    assert!(madt.has_8259);
+    assert!(madt.processors.iter().any(|p| p.enabled));
+    assert_eq!(madt.io_apics.len(), 1);
+    assert_eq!(madt.io_apics[0].address, PhysAddr::new(0xfec0_0000));
+    assert_eq!(madt.io_apics[0].gsi_base, 0);
+    // both machines send the PIT to GSI 2 and make the SCI (IRQ 9) level triggered
+    assert_eq!(
+        madt.isa_irq(0),
+        (2, Polarity::ActiveHigh, TriggerMode::Edge)
+    );
+    assert_eq!(madt.isa_irq(9).2, TriggerMode::Level);
+}
+
+#[test_case]
+fn fadt_has_the_pm_registers() {
+    let fadt = Fadt::get().unwrap();
+    assert_eq!(fadt.sci_interrupt, 9);
+    assert!(fadt.dsdt.as_u64() != 0);
+    assert_eq!(acpi::header_at(fadt.dsdt).signature, *b"DSDT");
+
+    let pm1a = fadt.pm1a_control_block.unwrap();
+    assert_eq!(pm1a.space, AddressSpace::SystemIo);
+    assert!(fadt.pm_timer_block.is_some());
+    // the century register is a CMOS index
+    assert!(fadt.century < 0x80);
+
+    if is_q35() {
+        let reset = fadt.reset_register.unwrap();
+        assert_eq!(reset.space, AddressSpace::SystemIo);
+        assert_eq!(reset.address, 0xcf9);
+    }
+}
+
+#[test_case]
+fn hpet_is_at_the_usual_address() {
+    let hpet = Hpet::get().unwrap();
+    assert_eq!(hpet.base_address, PhysAddr::new(0xfed0_0000));
+    assert!(hpet.comparators >= 3);
+}
+
+#[test_case]
+fn mcfg_only_on_q35() {
+    match Mcfg::get() {
+        Ok(mcfg) => {
+            let region = mcfg.region(0, 0).unwrap();
+            assert_eq!(region.bus_start, 0);
+            assert_eq!(
+                region.function_address(1, 2, 3),
+                Some(region.base_address + (1u64 << 20 | 2 << 15 | 3 << 12))
+            );
+            assert_eq!(region.function_address(0, 32, 0), None);
+        }
+        Err(err) => assert_eq!(err, AcpiError::TableNotFound(*b"MCFG")),
+    }
+}
@@ -0,0 +1,117 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader_api::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use kernel::memory::{
+    self,
+    address_space::{AddressSpace, switch_to_kernel},
+    mmio,
+};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageTableFlags, PhysFrame};
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// Start of a lower half level 4 slot the kernel doesn't use.
+fn private_addr() -> VirtAddr {
+    let mut mapper = memory::mapper();
+    let slot = (1..256)
+        .find(|&i| mapper.level_4_table_mut()[i].is_unused())
+        .expect("no free level 4 slot");
+    VirtAddr::new((slot as u64) << 39)
+}
+
+#[test_case]
+fn map_switch_and_back() {
+    let kernel_value = Box::new(1234u64);
+    let addr = private_addr();
+
+    let mut space = AddressSpace::new().expect("out of frames");
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    space.map_range(addr, 2 * 4096, flags).unwrap();
+    assert!(space.translate(addr).is_some());
+
+    unsafe {
+        space.switch();
+        let ptr: *mut u64 = addr.as_mut_ptr();
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+        // kernel memory is still there
+        assert_eq!(*kernel_value, 1234);
+        switch_to_kernel();
+    }
+    assert!(!space.is_active());
+}
+
+#[test_case]
+fn address_spaces_are_isolated() {
+    let addr = private_addr();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    let mut first = AddressSpace::new().unwrap();
+    let second = AddressSpace::new().unwrap();
+    first.map_range(addr, 4096, flags).unwrap();
+
+    assert!(first.translate(addr).is_some());
+    assert!(second.translate(addr).is_none());
+}
+
+#[test_case]
+fn later_kernel_mappings_are_shared() {
+    let space = AddressSpace::new().unwrap();
+    // both land in level 4 slots that had nothing mapped when the kernel booted
+    let stack = memory::stack::allocate("later", 4 * 4096).unwrap();
+    let frame: PhysFrame = memory::frame_allocator().allocate_frame().unwrap();
+    let region = unsafe { mmio::map_mmio(frame.start_address(), 4096) }.unwrap();
+
+    assert!(space.translate(stack.bottom).is_some());
+    assert_eq!(
+        space.translate(region.virt_addr()),
+        Some(frame.start_address())
+    );
+    drop(region);
+    unsafe { memory::frame_allocator().deallocate_frame(frame) };
+}
+
+#[test_case]
+fn unmap_and_drop_free_frames() {
+    let free_before = memory::frame_allocator().free_frames();
+    let addr = private_addr();
+
+    let mut space = AddressSpace::new().unwrap();
+    space
+        .map_range(
+            addr,
+            16 * 4096,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+        )
+        .unwrap();
+    space.unmap_range(addr, 8 * 4096);
+    assert!(space.translate(addr).is_none());
+    drop(space);
+
+    assert_eq!(memory::frame_allocator().free_frames(), free_before);
+}
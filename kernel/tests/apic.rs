@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader_api::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use kernel::acpi::{
+    self,
+    madt::{Madt, Polarity, TriggerMode},
+};
+use kernel::interrupts::{PICS, apic};
+use kernel::memory;
+use x86_64::VirtAddr;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+    unsafe { acpi::init(boot_info.rsdp_addr.into_option()) }.expect("ACPI init failed");
+    apic::init().expect("APIC init failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn pics_are_masked() {
+    assert!(apic::is_enabled());
+    assert_eq!(unsafe { PICS.lock().read_masks() }, [0xff, 0xff]);
+}
+
+#[test_case]
+fn local_apic_is_in_the_madt() {
+    let madt = Madt::get().unwrap();
+    let id = apic::local_apic().unwrap().id();
+    assert!(madt.processors.iter().any(|p| p.apic_id == id && p.enabled));
+}
+
+#[test_case]
+fn isa_overrides_are_applied() {
+    // QEMU wires the PIT (IRQ 0) to GSI 2, everything else is identity mapped
+    assert_eq!(
+        apic::isa_irq(0),
+        Some((2, Polarity::ActiveHigh, TriggerMode::Edge))
+    );
+    assert_eq!(apic::isa_irq(1).unwrap().0, 1);
+}
+
+#[test_case]
+fn timer_interrupts_arrive() {
+    // hlt only returns once an interrupt comes in, which now goes through the I/O APIC
+    for _ in 0..3 {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,185 @@
+//! Walks the frame pointer chain through a few nested calls, and resolves the return addresses
+//! with a symbol table made up here, as tests don't get the real one.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::ToString, vec::Vec};
+use bootloader_api::{BootInfo, entry_point};
+use core::hint::black_box;
+use core::panic::PanicInfo;
+use kernel::backtrace::{
+    self, Frame,
+    symbols::{self, SymbolError, SymbolTable},
+};
+use kernel::interrupts::exceptions::{self, Caught};
+use kernel::memory;
+use x86_64::VirtAddr;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+    kernel::gdt::init_stacks();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[inline(never)]
+fn start() -> Vec<Frame> {
+    black_box(outer())
+}
+
+#[inline(never)]
+fn outer() -> Vec<Frame> {
+    black_box(middle())
+}
+
+#[inline(never)]
+fn middle() -> Vec<Frame> {
+    black_box(inner())
+}
+
+#[inline(never)]
+fn inner() -> Vec<Frame> {
+    backtrace::trace().collect()
+}
+
+#[inline(never)]
+fn breakpoint() -> Caught {
+    unsafe {
+        exceptions::catch(exceptions::BREAKPOINT, 0, || {
+            x86_64::instructions::interrupts::int3();
+        })
+    }
+    .unwrap()
+}
+
+/// The frames the breakpoint handler saw, and the ones seen from here after it returned.
+#[inline(never)]
+fn breakpoint_caller() -> (Caught, Vec<Frame>) {
+    let caught = black_box(breakpoint());
+    (caught, backtrace::trace().collect())
+}
+
+/// A table in the format `build.rs` writes, `symbols` sorted by address.
+fn table(symbols: &[(u64, u32, &str)]) -> &'static [u8] {
+    let mut table = Vec::new();
+    let mut names = Vec::new();
+    table.extend_from_slice(b"POPCSYMS");
+    table.extend_from_slice(&(symbols.len() as u64).to_le_bytes());
+    for (address, size, name) in symbols {
+        table.extend_from_slice(&address.to_le_bytes());
+        table.extend_from_slice(&size.to_le_bytes());
+        table.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        table.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        table.extend_from_slice(&[0; 4]);
+        names.extend_from_slice(name.as_bytes());
+    }
+    table.extend_from_slice(&names);
+    table.leak()
+}
+
+#[test_case]
+fn walks_nested_calls() {
+    let frames = start();
+    assert!(frames.len() >= 3);
+
+    // no sizes, so each address resolves to whichever of these starts closest below it
+    let mut functions = [
+        (outer as usize as u64, 0, "outer"),
+        (middle as usize as u64, 0, "middle"),
+        (inner as usize as u64, 0, "inner"),
+        (start as usize as u64, 0, "start"),
+    ];
+    functions.sort_by_key(|&(address, _, _)| address);
+    let table = SymbolTable::parse(table(&functions), 0).unwrap();
+    let caller = |frame: &Frame| table.resolve(frame.return_address - 1u64).unwrap().name;
+    assert_eq!(caller(&frames[0]), "middle");
+    assert_eq!(caller(&frames[1]), "outer");
+    assert_eq!(caller(&frames[2]), "start");
+}
+
+#[test_case]
+fn walks_out_of_an_exception() {
+    let (caught, frames) = breakpoint_caller();
+    let interrupted: Vec<VirtAddr> = caught.return_addresses.iter().flatten().copied().collect();
+    assert!(
+        !interrupted.is_empty(),
+        "the handler's frame wasn't on the chain"
+    );
+
+    // above breakpoint_caller it's the same stack both times
+    let ours: Vec<VirtAddr> = frames.iter().map(|frame| frame.return_address).collect();
+    let start = interrupted
+        .iter()
+        .position(|&address| address == ours[0])
+        .expect("the interrupted frames don't reach breakpoint_caller's caller");
+    assert!(start >= 1, "breakpoint_caller itself is missing");
+    for (theirs, ours) in interrupted[start..].iter().zip(&ours) {
+        assert_eq!(theirs, ours);
+    }
+}
+
+#[test_case]
+fn resolves_with_offsets() {
+    let table = SymbolTable::parse(
+        table(&[
+            (0x1000, 0x20, "first"),
+            (0x1020, 0x10, "second"),
+            (0x2000, 0, "third"),
+        ]),
+        0xffff_8000_0000_0000,
+    )
+    .unwrap();
+    assert_eq!(table.len(), 3);
+
+    let resolve = |addr: u64| table.resolve(VirtAddr::new(0xffff_8000_0000_0000 + addr));
+    let symbol = resolve(0x1024).unwrap();
+    assert_eq!((symbol.name, symbol.offset), ("second", 4));
+    assert_eq!(symbol.to_string(), "second+0x4");
+    assert_eq!(resolve(0x1000).unwrap().name, "first");
+    // past the end of second, before third
+    assert_eq!(resolve(0x1030), None);
+    assert_eq!(resolve(0x0fff), None);
+    // no size, so anything after it counts
+    assert_eq!(resolve(0x2345).unwrap().offset, 0x345);
+}
+
+#[test_case]
+fn rejects_bad_tables() {
+    assert_eq!(
+        SymbolTable::parse(b"not a symbol table", 0).err(),
+        Some(SymbolError::BadMagic)
+    );
+    let mut truncated = table(&[(0x1000, 0x20, "first")]).to_vec();
+    truncated.truncate(30);
+    assert_eq!(
+        SymbolTable::parse(truncated.leak(), 0).err(),
+        Some(SymbolError::Truncated)
+    );
+}
+
+#[test_case]
+fn no_symbols_without_ramdisk() {
+    assert_eq!(symbols::resolve(VirtAddr::new(outer as usize as u64)), None);
+    assert_eq!(
+        unsafe { symbols::init(None, 0, 0) },
+        Err(SymbolError::NoRamdisk)
+    );
+}
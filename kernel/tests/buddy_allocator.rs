@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{BootInfo, entry_point};
+use conquer_once::spin::OnceCell;
+use core::panic::PanicInfo;
+use kernel::memory::buddy::{BuddyFrameAllocator, MAX_ORDER};
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+};
+
+static FRAME_ALLOCATOR: OnceCell<Mutex<BuddyFrameAllocator>> = OnceCell::uninit();
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    FRAME_ALLOCATOR.init_once(|| {
+        Mutex::new(unsafe { BuddyFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset) })
+    });
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn frame_allocator() -> spin::MutexGuard<'static, BuddyFrameAllocator> {
+    FRAME_ALLOCATOR.get().unwrap().lock()
+}
+
+fn free_blocks(allocator: &BuddyFrameAllocator) -> [usize; MAX_ORDER + 1] {
+    core::array::from_fn(|order| allocator.free_blocks(order))
+}
+
+#[test_case]
+fn allocate_and_free() {
+    let mut allocator = frame_allocator();
+    let free_before = allocator.free_frames();
+
+    let frame: PhysFrame<Size4KiB> = allocator.allocate_frame().expect("out of frames");
+    assert_eq!(allocator.free_frames(), free_before - 1);
+
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.free_frames(), free_before);
+}
+
+#[test_case]
+fn huge_frame_is_aligned() {
+    let mut allocator = frame_allocator();
+    let frame: PhysFrame<Size2MiB> = allocator.allocate_frame().expect("no free 2 MiB frame");
+    assert!(frame.start_address().is_aligned(Size2MiB::SIZE));
+    unsafe { allocator.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn split_frames_coalesce() {
+    let mut allocator = frame_allocator();
+    let blocks_before = free_blocks(&allocator);
+
+    // hand a 2 MiB frame back as 512 separate 4 KiB frames, they should merge back up
+    let huge: PhysFrame<Size2MiB> = allocator.allocate_frame().expect("no free 2 MiB frame");
+    let start = huge.start_address();
+    for i in 0..Size2MiB::SIZE / Size4KiB::SIZE {
+        let frame = PhysFrame::<Size4KiB>::containing_address(start + i * Size4KiB::SIZE);
+        unsafe { allocator.deallocate_frame(frame) };
+    }
+
+    assert_eq!(free_blocks(&allocator), blocks_before);
+}
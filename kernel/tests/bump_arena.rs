@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(allocator_api)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use bootloader_api::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use kernel::allocator::bump::BumpArena;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(_boot_info: &'static mut BootInfo) -> ! {
+    // no heap on purpose, everything here has to come out of the arenas
+    kernel::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn box_and_vec_in_arena() {
+    let mut region = [0u8; 1024];
+    let arena = BumpArena::new(&mut region);
+
+    let boxed = Box::new_in(41u64, &arena);
+    let mut vec = Vec::with_capacity_in(16, &arena);
+    vec.extend(0u32..16);
+
+    assert_eq!(*boxed + 1, 42);
+    assert_eq!(vec.iter().sum::<u32>(), 120);
+    assert_eq!(&*boxed as *const u64 as usize % 8, 0);
+    assert!(arena.used() >= 8 + 16 * 4);
+}
+
+#[test_case]
+fn mark_and_reset() {
+    let mut region = [0u8; 256];
+    let mut arena = BumpArena::new(&mut region);
+    core::mem::forget(Box::new_in([1u8; 32], &arena));
+    let mark = arena.mark();
+    let used = arena.used();
+
+    // freeing these out of order doesn't give anything back, only the reset does
+    let scratch = Box::new_in([2u8; 64], &arena);
+    let more = Box::new_in([3u8; 64], &arena);
+    drop(scratch);
+    core::mem::forget(more);
+    assert!(arena.used() >= used + 128);
+
+    arena.reset_to(mark);
+    assert_eq!(arena.used(), used);
+
+    arena.reset();
+    assert_eq!(arena.used(), 0);
+    assert_eq!(arena.remaining(), 256);
+}
+
+#[test_case]
+fn latest_allocation_is_given_back() {
+    let mut region = [0u8; 128];
+    let arena = BumpArena::new(&mut region);
+    let first = Box::new_in(1u64, &arena);
+    let used = arena.used();
+    drop(Box::new_in(2u64, &arena));
+    assert_eq!(arena.used(), used);
+    drop(first);
+    assert_eq!(arena.used(), 0);
+}
+
+#[test_case]
+fn full_arena_fails_allocation() {
+    let mut region = [0u8; 64];
+    let arena = BumpArena::new(&mut region);
+    assert!(Box::try_new_in([0u8; 48], &arena).is_ok());
+    assert!(Box::try_new_in([0u8; 48], &arena).is_err());
+}
@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use kernel::memory::{
+    self,
+    vma::{self, Backing},
+};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{PageTableFlags, Translate};
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// Start of a lower half level 4 slot the kernel doesn't use.
+fn free_addr() -> VirtAddr {
+    let mut mapper = memory::mapper();
+    let slot = (1..256)
+        .find(|&i| mapper.level_4_table_mut()[i].is_unused())
+        .expect("no free level 4 slot");
+    VirtAddr::new((slot as u64) << 39)
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    memory::mapper().translate_addr(addr).is_some()
+}
+
+#[test_case]
+fn pages_are_mapped_on_first_touch() {
+    let start = free_addr();
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    vma::reserve(start, 16 * 4096, flags, Backing::Anonymous).unwrap();
+    assert!(!is_mapped(start));
+
+    let second_page: *mut u64 = (start + 4096u64).as_mut_ptr();
+    unsafe {
+        assert_eq!(second_page.read_volatile(), 0); // anonymous memory starts zeroed
+        second_page.write_volatile(0xcafe);
+        assert_eq!(second_page.read_volatile(), 0xcafe);
+    }
+    assert!(is_mapped(start + 4096u64));
+    assert!(!is_mapped(start)); // only the touched page
+
+    vma::release(start).unwrap();
+    assert!(!is_mapped(start + 4096u64));
+}
+
+#[test_case]
+fn release_frees_frames() {
+    let start = free_addr() + (1u64 << 30);
+    let free_before = memory::frame_allocator().free_frames();
+
+    vma::reserve(
+        start,
+        4 * 4096,
+        PageTableFlags::WRITABLE,
+        Backing::Anonymous,
+    )
+    .unwrap();
+    for i in 0..4u64 {
+        let ptr: *mut u8 = (start + i * 4096).as_mut_ptr();
+        unsafe { ptr.write_volatile(1) };
+    }
+    vma::release(start).unwrap();
+
+    // the page tables created for the area stay around, only the pages go back
+    let free_after = memory::frame_allocator().free_frames();
+    assert!(free_before - free_after < 4);
+}
+
+#[test_case]
+fn overlapping_areas_are_rejected() {
+    let start = free_addr() + (2u64 << 30);
+    let area = vma::reserve(
+        start,
+        4 * 4096,
+        PageTableFlags::WRITABLE,
+        Backing::Anonymous,
+    )
+    .unwrap();
+    assert_eq!(
+        vma::reserve(
+            start + 4096u64,
+            4096,
+            PageTableFlags::WRITABLE,
+            Backing::Anonymous
+        ),
+        Err(area)
+    );
+    assert_eq!(vma::find(start + 8192u64), Some(area));
+    vma::release(start);
+}
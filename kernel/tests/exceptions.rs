@@ -0,0 +1,251 @@
+//! Raises the exceptions that can be raised from ring 0 and checks they reach their handlers,
+//! using `exceptions::catch` to step over the faulting instruction. The instruction lengths
+//! passed to `catch` depend on the exact registers in the `asm!` blocks.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader_api::{BootInfo, entry_point};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use kernel::interrupts::exceptions::mca::{self, BankError, McaErrorCode};
+use kernel::interrupts::exceptions::{self, ErrorCode};
+use kernel::memory;
+use x86_64::VirtAddr;
+use x86_64::registers::control::{Cr2, Cr4, Cr4Flags};
+use x86_64::structures::idt::{DescriptorTable, PageFaultErrorCode};
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn divide_error() {
+    let caught = unsafe {
+        exceptions::catch(exceptions::DIVIDE_ERROR, 2, || {
+            // div ecx: f7 f1
+            asm!("div ecx", in("ecx") 0, inout("eax") 1 => _, inout("edx") 0 => _);
+        })
+    };
+    assert_eq!(caught.unwrap().error_code, ErrorCode::None);
+}
+
+#[test_case]
+fn invalid_opcode() {
+    let caught = unsafe {
+        exceptions::catch(exceptions::INVALID_OPCODE, 2, || {
+            asm!("ud2");
+        })
+    };
+    let caught = caught.unwrap();
+    // the instruction pointer is the ud2 itself
+    let bytes = unsafe { caught.instruction_pointer.as_ptr::<[u8; 2]>().read() };
+    assert_eq!(bytes, [0x0f, 0x0b]);
+}
+
+#[test_case]
+fn breakpoint() {
+    // a trap, the instruction pointer is already past the int3
+    let caught = unsafe {
+        exceptions::catch(exceptions::BREAKPOINT, 0, || {
+            x86_64::instructions::interrupts::int3();
+        })
+    };
+    assert!(caught.is_some());
+    // and without catch it's logged and execution just continues
+    x86_64::instructions::interrupts::int3();
+}
+
+#[test_case]
+fn general_protection_fault_decodes_the_selector() {
+    let caught = unsafe {
+        exceptions::catch(exceptions::GENERAL_PROTECTION_FAULT, 2, || {
+            // mov ds, eax: 8e d8, the selector is way past the end of the GDT
+            asm!("mov ds, eax", in("eax") 0x1234u32);
+        })
+    };
+    let Some(code) = caught.unwrap().error_code.selector() else {
+        panic!("not a selector error code");
+    };
+    assert_eq!(code.index(), 0x1234 >> 3);
+    assert_eq!(code.descriptor_table(), DescriptorTable::Gdt);
+    assert!(!code.external());
+}
+
+#[test_case]
+fn general_protection_fault_on_non_canonical_address() {
+    let caught = unsafe {
+        exceptions::catch(exceptions::GENERAL_PROTECTION_FAULT, 3, || {
+            // mov rax, [rcx]: 48 8b 01
+            asm!("mov rax, [rcx]", in("rcx") 0x8000_0000_0000_0000u64, out("rax") _);
+        })
+    };
+    assert_eq!(caught.unwrap().error_code.bits(), Some(0));
+}
+
+#[test_case]
+fn stack_segment_fault() {
+    let caught = unsafe {
+        exceptions::catch(exceptions::STACK_SEGMENT_FAULT, 4, || {
+            // mov rax, [rsp + rcx]: 48 8b 04 0c, non-canonical through the stack segment
+            asm!("mov rax, [rsp + rcx]", in("rcx") 0x8000_0000_0000_0000u64, out("rax") _);
+        })
+    };
+    assert_eq!(caught.unwrap().error_code.bits(), Some(0));
+}
+
+#[test_case]
+fn page_fault() {
+    // reserved address space with nothing mapped (or reserved for demand paging) behind it
+    let addr = memory::virt::allocate(4096, 4096).unwrap();
+    let caught = unsafe {
+        exceptions::catch(exceptions::PAGE_FAULT, 2, || {
+            // mov cl, [rax]: 8a 08
+            asm!("mov cl, [rax]", in("rax") addr.as_u64(), out("cl") _);
+        })
+    };
+    assert_eq!(
+        caught.unwrap().error_code,
+        ErrorCode::PageFault(PageFaultErrorCode::empty())
+    );
+    assert_eq!(Cr2::read().unwrap(), addr);
+    memory::virt::free(addr, 4096);
+}
+
+/// Vectors without an error code can be raised with `int n`, which is a trap like int3.
+macro_rules! software_interrupt {
+    ($vector:expr) => {{
+        let caught = unsafe { exceptions::catch($vector, 0, || {
+            asm!("int {vector}", vector = const $vector);
+        }) };
+        assert_eq!(caught.map(|caught| caught.vector), Some($vector));
+    }};
+}
+
+#[test_case]
+fn vectors_without_error_codes() {
+    software_interrupt!(exceptions::DEBUG);
+    software_interrupt!(exceptions::NON_MASKABLE_INTERRUPT);
+    software_interrupt!(exceptions::OVERFLOW);
+    software_interrupt!(exceptions::BOUND_RANGE_EXCEEDED);
+    software_interrupt!(exceptions::DEVICE_NOT_AVAILABLE);
+    software_interrupt!(exceptions::X87_FLOATING_POINT);
+    software_interrupt!(exceptions::SIMD_FLOATING_POINT);
+    software_interrupt!(exceptions::VIRTUALIZATION);
+    software_interrupt!(exceptions::HV_INJECTION);
+}
+
+// #TS, #NP, #AC, #CP, #VC and #SX push an error code, so `int n` would leave a frame their
+// handlers misread, and they can't be raised for real from ring 0 without a task switch, a
+// not-present descriptor or user mode. Their decoding is checked here instead.
+#[test_case]
+fn error_codes_are_decoded_by_vector() {
+    let Some(code) = ErrorCode::new(exceptions::SEGMENT_NOT_PRESENT, 0x1233).selector() else {
+        panic!("not a selector error code");
+    };
+    assert_eq!(code.index(), 0x246);
+    assert_eq!(code.descriptor_table(), DescriptorTable::Idt);
+    assert!(code.external());
+    assert_eq!(
+        ErrorCode::new(exceptions::INVALID_TSS, 0x1233).bits(),
+        Some(0x1233)
+    );
+    // a table field of 0b11 is the IDT too, but the code has to come back as the CPU pushed it
+    let idt_again = ErrorCode::new(exceptions::GENERAL_PROTECTION_FAULT, 0x1236);
+    assert_eq!(idt_again.bits(), Some(0x1236));
+    assert_eq!(
+        idt_again.selector().unwrap().descriptor_table(),
+        DescriptorTable::Idt
+    );
+    assert_eq!(
+        ErrorCode::new(exceptions::ALIGNMENT_CHECK, 0),
+        ErrorCode::Raw(0)
+    );
+    assert_eq!(
+        ErrorCode::new(exceptions::PAGE_FAULT, 0b11),
+        ErrorCode::PageFault(
+            PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE
+        )
+    );
+}
+
+#[test_case]
+fn every_vector_has_a_name() {
+    assert_eq!(exceptions::name(13), "general protection fault");
+    assert_eq!(exceptions::mnemonic(13), "GP");
+    assert_eq!(exceptions::mnemonic(exceptions::CONTROL_PROTECTION), "CP");
+    assert_eq!(exceptions::mnemonic(exceptions::VMM_COMMUNICATION), "VC");
+    assert_eq!(exceptions::mnemonic(exceptions::SECURITY), "SX");
+    assert_eq!(exceptions::mnemonic(exceptions::MACHINE_CHECK), "MC");
+    assert_eq!(exceptions::mnemonic(exceptions::DOUBLE_FAULT), "DF");
+}
+
+#[test_case]
+fn nmis_are_counted() {
+    let before = exceptions::nmi_count();
+    unsafe { asm!("int 2") };
+    assert_eq!(exceptions::nmi_count(), before + 1);
+    // the handler only leaves a note, this logs it
+    exceptions::report_nmis();
+}
+
+#[test_case]
+fn machine_check_banks_start_clear() {
+    if mca::is_supported() {
+        assert!(mca::bank_count() > 0);
+        assert!(Cr4::read().contains(Cr4Flags::MACHINE_CHECK_EXCEPTION));
+    }
+    assert_eq!(mca::errors().count(), 0);
+}
+
+#[test_case]
+fn machine_check_codes_are_decoded() {
+    let decode = |code| alloc::format!("{}", McaErrorCode(code));
+    assert_eq!(decode(0x0005), "internal parity error");
+    assert_eq!(decode(0x0406), "internal unclassified error");
+    assert_eq!(decode(0x000e), "L2 cache hierarchy error");
+    assert_eq!(decode(0x0010), "L0 instruction TLB error");
+    assert_eq!(decode(0x009f), "memory controller read error");
+    assert_eq!(
+        decode(0x10a2),
+        "memory controller write error on channel 2, filtered"
+    );
+    assert_eq!(decode(0x0135), "L1 data cache data read error");
+    assert_eq!(
+        decode(0x0f0f),
+        "bus error, generic, generic request, timed out"
+    );
+    assert_eq!(decode(0x0e0b), "I/O error");
+
+    let error = BankError {
+        bank: 3,
+        status: BankError::VALID | BankError::UNCORRECTED | BankError::ADDRESS_VALID | 0x009f,
+        address: Some(0x1234_5000),
+        misc: None,
+    };
+    assert!(error.is_uncorrected());
+    assert_eq!(
+        alloc::format!("{error}"),
+        "bank 3: uncorrected memory controller read error (status 0xa40000000000009f, model \
+         specific 0x0000), address 0x12345000"
+    );
+}
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{BootInfo, entry_point};
+use conquer_once::spin::OnceCell;
+use core::panic::PanicInfo;
+use kernel::memory::bitmap::BitmapFrameAllocator;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+
+static FRAME_ALLOCATOR: OnceCell<Mutex<BitmapFrameAllocator>> = OnceCell::uninit();
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    FRAME_ALLOCATOR.init_once(|| {
+        Mutex::new(unsafe {
+            BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+        })
+    });
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn frame_allocator() -> spin::MutexGuard<'static, BitmapFrameAllocator> {
+    FRAME_ALLOCATOR.get().unwrap().lock()
+}
+
+#[test_case]
+fn allocate_and_free() {
+    let mut allocator = frame_allocator();
+    let free_before = allocator.free_frames();
+
+    let frame = allocator.allocate_frame().expect("out of frames");
+    assert_eq!(allocator.free_frames(), free_before - 1);
+
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.free_frames(), free_before);
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+    let mut allocator = frame_allocator();
+    let first = allocator.allocate_frame().unwrap();
+    let second = allocator.allocate_frame().unwrap();
+    assert_ne!(first, second);
+
+    unsafe { allocator.deallocate_frame(first) };
+    assert_eq!(allocator.allocate_frame(), Some(first));
+
+    unsafe {
+        allocator.deallocate_frame(first);
+        allocator.deallocate_frame(second);
+    }
+}
+
+#[test_case]
+fn counts_add_up() {
+    let allocator = frame_allocator();
+    assert_eq!(
+        allocator.free_frames() + allocator.used_frames(),
+        allocator.total_frames()
+    );
+    assert!(allocator.free_frames() > 0);
+}
@@ -1,3 +1,6 @@
+//! Runs against whichever global allocator the `alloc-*` features picked, `test-allocators.sh`
+//! goes through all of them.
+
 #![no_std]
 #![no_main]
 #![feature(custom_test_frameworks)]
@@ -9,13 +12,12 @@ extern crate alloc;
 use alloc::{boxed::Box, vec::Vec};
 use bootloader_api::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use kernel::allocator::HEAP_SIZE;
+use kernel::allocator::{self, HEAP_SIZE, stats::Checkpoint};
 
 entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
 
 fn main(boot_info: &'static mut BootInfo) -> ! {
-    use kernel::allocator;
-    use kernel::memory::{self, BootInfoFrameAllocator};
+    use kernel::memory;
     use x86_64::VirtAddr;
 
     kernel::init();
@@ -24,9 +26,8 @@ fn main(boot_info: &'static mut BootInfo) -> ! {
     unsafe { kernel::init_logger(frame_buffer_struct.buffer_mut(), frame_buffer_info) };
     let phys_mem_offset =
         VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap init failed :(");
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    allocator::init_heap().expect("heap init failed :(");
 
     test_main();
     loop {}
@@ -73,3 +74,67 @@ fn many_boxes_long_lived() {
     }
     assert_eq!(*longer, 20);
 }
+
+#[test_case]
+fn grows_past_initial_size() {
+    let size = HEAP_SIZE * 4;
+    let mut vec = Vec::<u8>::with_capacity(size);
+    vec.resize(size, 0xaa);
+    assert!(vec.iter().all(|&byte| byte == 0xaa));
+}
+
+#[test_case]
+fn no_leaks_after_drop() {
+    let checkpoint = Checkpoint::new();
+    {
+        let small = Box::new(42u32);
+        let big = Vec::<u8>::with_capacity(8192);
+        assert_eq!(checkpoint.net_allocations(), 2);
+        drop((small, big));
+    }
+    checkpoint.assert_no_leaks();
+}
+
+// bump and linked list allocations all go to the fallback heap
+#[cfg(not(any(feature = "alloc-bump", feature = "alloc-linked-list")))]
+#[test_case]
+fn stats_track_size_classes() {
+    let before = allocator::stats();
+    let value = Box::new([0u8; 30]);
+    let during = allocator::stats();
+
+    // 30 bytes land in the 32 byte class
+    let class = during
+        .size_classes
+        .iter()
+        .position(|c| c.block_size == 32)
+        .unwrap();
+    assert_eq!(
+        during.size_classes[class].allocated,
+        before.size_classes[class].allocated + 1
+    );
+    assert!(during.peak_bytes_in_use >= during.bytes_in_use);
+    drop(value);
+}
+
+#[test_case]
+fn memory_stats_add_up() {
+    use kernel::memory;
+    use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+
+    let before = memory::stats();
+    assert_eq!(before.allocated_frames + before.free_frames, before.total_frames);
+    assert!(before.usable_bytes <= before.total_bytes);
+    assert!(before.total_frames as u64 * 4096 <= before.usable_bytes);
+
+    let boxed = Box::new([0u8; 512]);
+    let with_box = memory::stats();
+    assert!(with_box.heap_used >= before.heap_used + 512);
+
+    // the box can grow the heap, so frames are compared from here on
+    let frame: PhysFrame = memory::frame_allocator().allocate_frame().unwrap();
+    assert_eq!(memory::stats().allocated_frames, with_box.allocated_frames + 1);
+    unsafe { memory::frame_allocator().deallocate_frame(frame) };
+    assert_eq!(memory::stats().allocated_frames, with_box.allocated_frames);
+    drop(boxed);
+}
@@ -0,0 +1,115 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc};
+use bootloader_api::{BootInfo, entry_point};
+use core::alloc::Layout;
+use core::panic::PanicInfo;
+use kernel::allocator::{self, debug};
+use kernel::memory;
+use x86_64::VirtAddr;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    allocator::init_heap().expect("heap init failed :(");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn clean_use_reports_nothing() {
+    let before = debug::corruptions();
+    let small = Layout::from_size_align(48, 8).unwrap();
+    let large = Layout::from_size_align(8000, 8).unwrap();
+    unsafe {
+        let a = alloc(small);
+        let b = alloc(large);
+        a.write_bytes(1, small.size());
+        b.write_bytes(2, large.size());
+        dealloc(a, small);
+        dealloc(b, large);
+    }
+    assert_eq!(debug::corruptions(), before);
+}
+
+#[test_case]
+fn new_allocations_are_poisoned() {
+    let layout = Layout::from_size_align(100, 4).unwrap();
+    unsafe {
+        let ptr = alloc(layout);
+        assert_eq!(*ptr.add(99), debug::ALLOC_POISON);
+        dealloc(ptr, layout);
+    }
+}
+
+#[test_case]
+fn write_after_free_is_caught() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let before = debug::corruptions();
+    unsafe {
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        assert_eq!(*ptr.add(40), debug::FREE_POISON);
+        ptr.add(40).write_volatile(0);
+
+        // the block is at the head of its free list, so it comes straight back
+        let again = alloc(layout);
+        assert_eq!(again, ptr);
+        dealloc(again, layout);
+    }
+    assert_eq!(debug::corruptions(), before + 1);
+}
+
+#[test_case]
+fn double_free_is_caught() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let before = debug::corruptions();
+    unsafe {
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        dealloc(ptr, layout);
+        assert_eq!(debug::corruptions(), before + 1);
+
+        // the second free was dropped, so the block is only handed out once
+        let first = alloc(layout);
+        let second = alloc(layout);
+        assert_ne!(first, second);
+        dealloc(first, layout);
+        dealloc(second, layout);
+    }
+}
+
+#[test_case]
+fn overflow_into_red_zone_is_caught() {
+    let layout = Layout::from_size_align(4096, 16).unwrap();
+    let before = debug::corruptions();
+    unsafe {
+        let ptr = alloc(layout);
+        ptr.add(layout.size()).write_volatile(0);
+        dealloc(ptr, layout);
+    }
+    assert_eq!(debug::corruptions(), before + 1);
+
+    unsafe {
+        let ptr = alloc(layout);
+        ptr.sub(1).write_volatile(0);
+        dealloc(ptr, layout);
+    }
+    assert_eq!(debug::corruptions(), before + 2);
+}
@@ -0,0 +1,122 @@
+//! Registers handlers on IRQ lines at runtime. The timer line fires on its own, the rest are
+//! raised with `int` on the line's vector.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader_api::{BootInfo, entry_point};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use kernel::interrupts::irq::{self, IrqError, IrqReturn};
+use kernel::memory;
+use x86_64::VirtAddr;
+use x86_64::instructions::hlt;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn counter(result: IrqReturn) -> (Arc<AtomicU64>, impl Fn() -> IrqReturn + Send + Sync) {
+    let count = Arc::new(AtomicU64::new(0));
+    let handler_count = count.clone();
+    let handler = move || {
+        handler_count.fetch_add(1, Ordering::Relaxed);
+        result
+    };
+    (count, handler)
+}
+
+#[test_case]
+fn timer_handler_runs() {
+    let (count, handler) = counter(IrqReturn::Handled);
+    let handle = irq::register_irq(0, handler).unwrap();
+    for _ in 0..5 {
+        hlt();
+    }
+    assert!(count.load(Ordering::Relaxed) > 0);
+
+    drop(handle);
+    let after = count.load(Ordering::Relaxed);
+    for _ in 0..5 {
+        hlt();
+    }
+    assert_eq!(count.load(Ordering::Relaxed), after);
+}
+
+#[test_case]
+fn shared_line() {
+    let (first, first_handler) = counter(IrqReturn::NotMine);
+    let (second, second_handler) = counter(IrqReturn::Handled);
+    let _first = irq::register_irq(11, first_handler).unwrap();
+    let _second = irq::register_irq(11, second_handler).unwrap();
+    assert!(irq::has_handlers(11));
+
+    let before = irq::count(11);
+    unsafe { asm!("int 43") };
+    assert_eq!(irq::count(11), before + 1);
+    assert_eq!(first.load(Ordering::Relaxed), 1);
+    assert_eq!(second.load(Ordering::Relaxed), 1);
+}
+
+#[test_case]
+fn unclaimed_interrupt() {
+    let (count, handler) = counter(IrqReturn::NotMine);
+    let handle = irq::register_irq(10, handler).unwrap();
+    let before = irq::unhandled(10);
+    unsafe { asm!("int 42") };
+    assert_eq!(count.load(Ordering::Relaxed), 1);
+    assert_eq!(irq::unhandled(10), before + 1);
+
+    drop(handle);
+    assert!(!irq::has_handlers(10));
+}
+
+/// `raise` uses `int`, so nothing is in service on the PICs and the interrupt looks spurious.
+fn check_spurious(irq: u8, raise: fn()) {
+    let (count, handler) = counter(IrqReturn::Handled);
+    let _handle = irq::register_irq(irq, handler).unwrap();
+    let (before, spurious) = (irq::count(irq), irq::spurious(irq));
+    raise();
+    assert_eq!(irq::spurious(irq), spurious + 1);
+    assert_eq!(irq::count(irq), before);
+    assert_eq!(count.load(Ordering::Relaxed), 0);
+}
+
+#[test_case]
+fn spurious_interrupts_skip_handlers() {
+    check_spurious(7, || unsafe { asm!("int 39") });
+    check_spurious(15, || unsafe { asm!("int 47") });
+}
+
+#[test_case]
+fn invalid_lines() {
+    assert_eq!(
+        irq::register_irq(16, || IrqReturn::Handled).err(),
+        Some(IrqError::InvalidIrq(16))
+    );
+    assert_eq!(
+        irq::register_irq(2, || IrqReturn::Handled).err(),
+        Some(IrqError::Cascade)
+    );
+}
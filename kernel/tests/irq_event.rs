@@ -0,0 +1,115 @@
+//! Feeds `IrqEvent` and `IrqQueue` from a handler on IRQ 11, raised with `int 43`.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, task::Wake};
+use bootloader_api::{BootInfo, entry_point};
+use core::arch::asm;
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::task::{Context, Poll, Waker};
+use futures_util::Stream;
+use kernel::interrupts::irq::{self, IrqReturn};
+use kernel::memory;
+use kernel::task::irq::{IrqEvent, IrqQueue};
+use x86_64::VirtAddr;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+struct FlagWaker(AtomicBool);
+
+impl Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+fn flag_waker() -> (Arc<FlagWaker>, Waker) {
+    let flag = Arc::new(FlagWaker(AtomicBool::new(false)));
+    (flag.clone(), Waker::from(flag))
+}
+
+fn raise() {
+    unsafe { asm!("int 43") };
+}
+
+#[test_case]
+fn event_wakes_waiter() {
+    static EVENT: IrqEvent = IrqEvent::new();
+    let _handle = irq::register_irq(11, || {
+        EVENT.signal();
+        IrqReturn::Handled
+    })
+    .unwrap();
+
+    let (flag, waker) = flag_waker();
+    let mut context = Context::from_waker(&waker);
+    let mut wait = pin!(EVENT.wait());
+    assert_eq!(wait.as_mut().poll(&mut context), Poll::Pending);
+
+    raise();
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert_eq!(wait.as_mut().poll(&mut context), Poll::Ready(1));
+
+    // signals while nobody waits are counted for the next wait
+    raise();
+    raise();
+    assert_eq!(pin!(EVENT.wait()).poll(&mut context), Poll::Ready(2));
+    assert_eq!(EVENT.take(), 0);
+}
+
+#[test_case]
+fn queue_streams_values() {
+    static QUEUE: IrqQueue<u8> = IrqQueue::new(2);
+    static NEXT: AtomicU8 = AtomicU8::new(0);
+    let _handle = irq::register_irq(11, || {
+        let _ = QUEUE.push(NEXT.fetch_add(1, Ordering::Relaxed));
+        IrqReturn::Handled
+    })
+    .unwrap();
+
+    // no stream yet, so there's no queue
+    raise();
+    assert_eq!(QUEUE.dropped(), 1);
+
+    let (flag, waker) = flag_waker();
+    let mut context = Context::from_waker(&waker);
+    let mut stream = pin!(QUEUE.stream());
+    assert_eq!(stream.as_mut().poll_next(&mut context), Poll::Pending);
+
+    raise();
+    assert!(flag.0.load(Ordering::SeqCst));
+    raise();
+    raise();
+    assert_eq!(QUEUE.dropped(), 2);
+    assert_eq!(
+        stream.as_mut().poll_next(&mut context),
+        Poll::Ready(Some(1))
+    );
+    assert_eq!(stream.try_next(), Some(2));
+    assert_eq!(stream.try_next(), None);
+}
@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use kernel::memory::{
+    self,
+    mmio::{self, CachePolicy},
+    virt::{self, VirtualRangeAllocator},
+};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageTableFlags, PhysFrame, Translate, mapper::TranslateResult,
+};
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn flags_of(addr: VirtAddr) -> Option<PageTableFlags> {
+    match memory::mapper().translate(addr) {
+        TranslateResult::Mapped { flags, .. } => Some(flags),
+        _ => None,
+    }
+}
+
+#[test_case]
+fn ranges_are_reused_and_merged() {
+    let mut ranges = VirtualRangeAllocator::new(0x10_0000, 16 * 4096);
+    let a = ranges.allocate(4096, 4096).unwrap();
+    let b = ranges.allocate(2 * 4096, 4096).unwrap();
+    let c = ranges.allocate(4096, 4096).unwrap();
+    assert_eq!(b, a + 4096u64);
+    assert_eq!(c, b + 2 * 4096u64);
+
+    // a and b merge into one hole that fits three pages
+    ranges.free(a, 4096);
+    ranges.free(b, 2 * 4096);
+    assert_eq!(ranges.allocate(3 * 4096, 4096), Some(a));
+
+    ranges.free(a, 3 * 4096);
+    ranges.free(c, 4096);
+    assert_eq!(ranges.free_bytes(), 16 * 4096);
+    assert_eq!(ranges.allocate(16 * 4096, 4096), Some(a));
+    assert_eq!(ranges.allocate(4096, 4096), None);
+}
+
+#[test_case]
+fn ranges_are_aligned() {
+    let mut ranges = VirtualRangeAllocator::new(0x20_1000, 0x80_0000);
+    let aligned = ranges.allocate(4096, 0x20_0000).unwrap();
+    assert_eq!(aligned.as_u64(), 0x40_0000);
+
+    // the skipped gap below the aligned range is still usable
+    assert_eq!(ranges.allocate(4096, 4096).unwrap().as_u64(), 0x20_1000);
+}
+
+#[test_case]
+fn mmio_region_maps_the_frame() {
+    let frame: PhysFrame = memory::frame_allocator().allocate_frame().unwrap();
+    let free_before = virt::free_bytes();
+    let offset = 0x10;
+    let region = unsafe { mmio::map_mmio(frame.start_address() + offset as u64, 64) }.unwrap();
+
+    assert_eq!(region.virt_addr().as_u64() % 4096, offset as u64);
+    let flags = flags_of(region.virt_addr()).unwrap();
+    assert!(flags.contains(CachePolicy::Uncacheable.flags()));
+    assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+
+    // only through the region, reading RAM through the write-back physical memory mapping while
+    // it's mapped uncacheable here is the alias `map_mmio_with` warns about
+    region.write::<u32>(4, 0x1234_5678);
+    assert_eq!(region.read::<u32>(4), 0x1234_5678);
+
+    let virt_addr = region.virt_addr();
+    drop(region);
+    assert!(flags_of(virt_addr).is_none());
+    assert_eq!(virt::free_bytes(), free_before);
+    unsafe { memory::frame_allocator().deallocate_frame(frame) };
+}
+
+#[test_case]
+fn write_combining_uses_pwt_only() {
+    let frame: PhysFrame = memory::frame_allocator().allocate_frame().unwrap();
+    let region =
+        unsafe { mmio::map_mmio_with(frame.start_address(), 4096, CachePolicy::WriteCombining) }
+            .unwrap();
+
+    let flags = flags_of(region.virt_addr()).unwrap();
+    assert!(flags.contains(PageTableFlags::WRITE_THROUGH));
+    assert!(!flags.contains(PageTableFlags::NO_CACHE));
+
+    drop(region);
+    unsafe { memory::frame_allocator().deallocate_frame(frame) };
+}
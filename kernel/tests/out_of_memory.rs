@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader_api::{BootInfo, entry_point};
+use core::alloc::Layout;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::allocator::{self, oom};
+use kernel::memory;
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    allocator::init_heap().expect("heap init failed :(");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+const CHUNK: usize = 256 * 1024;
+
+/// Memory the pressure callback gives back.
+static BALLAST: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+static CALLBACK_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+fn drop_ballast(_layout: &Layout) {
+    CALLBACK_RUNS.fetch_add(1, Ordering::Relaxed);
+    // `exhaust_heap` only takes the ballast lock once it's done allocating, so this can't spin
+    let ballast = core::mem::take(&mut *BALLAST.lock());
+    drop(ballast);
+}
+
+/// Allocates `CHUNK`s until the heap won't give out any more, then hands them to the ballast.
+fn exhaust_heap() {
+    let mut ballast = Vec::with_capacity(1024);
+    loop {
+        let mut chunk = Vec::new();
+        if chunk.try_reserve_exact(CHUNK).is_err() {
+            break;
+        }
+        ballast.push(chunk);
+        assert!(ballast.len() < ballast.capacity(), "heap never ran out");
+    }
+    // swapping doesn't allocate, and whatever was there before is freed outside the lock
+    let previous = core::mem::replace(&mut *BALLAST.lock(), ballast);
+    drop(previous);
+}
+
+#[test_case]
+fn exhausted_heap_fails_fallible_allocation() {
+    let failures = oom::failures();
+    exhaust_heap();
+    assert_eq!(oom::failures(), failures + 1);
+
+    // the heap grew as far as it could before giving up
+    assert!(allocator::stats().heap_size > allocator::HEAP_SIZE);
+    BALLAST.lock().clear();
+}
+
+#[test_case]
+fn pressure_callback_makes_room() {
+    exhaust_heap();
+    oom::register_pressure_callback(drop_ballast);
+
+    // infallible, so this only gets through because the callback freed the ballast
+    let failures = oom::failures();
+    let mut vec = Vec::<u8>::with_capacity(CHUNK);
+    vec.push(1);
+    assert_eq!(CALLBACK_RUNS.load(Ordering::Relaxed), 1);
+    assert_eq!(oom::failures(), failures);
+    assert!(BALLAST.lock().is_empty());
+}
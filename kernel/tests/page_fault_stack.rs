@@ -0,0 +1,92 @@
+//! Overflows the boot stack with the kernel's own IDT loaded. The page fault handler has to run
+//! on its IST stack to get anywhere, and it panics, so the check happens in the panic handler.
+
+#![no_std]
+#![no_main]
+
+use bootloader_api::{BootInfo, entry_point};
+use core::{arch::asm, hint::black_box, panic::PanicInfo};
+use kernel::interrupts::exceptions;
+use kernel::{QemuExitCode, exit_qemu, gdt, memory, serial_print, serial_println};
+use x86_64::VirtAddr;
+use x86_64::instructions::tables::sidt;
+use x86_64::registers::control::Cr2;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    serial_print!("page_fault_stack::ist_indices...\t");
+    gdt::init();
+    kernel::interrupts::init_idt();
+    check_ist_indices();
+    serial_println!("[ok]");
+
+    serial_print!("page_fault_stack::stack_overflow...\t");
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+    gdt::init_stacks();
+
+    black_box(overflow(0));
+    fail(format_args!("execution continued after the stack overflow"));
+}
+
+/// The IST field is the low 3 bits of byte 4 of each 16 byte gate, 1 based as 0 means none.
+fn ist_index(vector: u8) -> u8 {
+    let idt = sidt().base;
+    let gate = idt + u64::from(vector) * 16;
+    unsafe { (gate + 4u64).as_ptr::<u8>().read() & 0b111 }
+}
+
+fn check_ist_indices() {
+    let expected = [
+        (exceptions::DOUBLE_FAULT, gdt::DOUBLE_FAULT_IST_INDEX),
+        (exceptions::NON_MASKABLE_INTERRUPT, gdt::NMI_IST_INDEX),
+        (exceptions::MACHINE_CHECK, gdt::MACHINE_CHECK_IST_INDEX),
+        (exceptions::PAGE_FAULT, gdt::PAGE_FAULT_IST_INDEX),
+    ];
+    for (vector, index) in expected {
+        let actual = ist_index(vector);
+        if u16::from(actual) != index + 1 {
+            fail(format_args!(
+                "{} is on IST entry {actual}, expected {}",
+                exceptions::name(vector),
+                index + 1
+            ));
+        }
+    }
+}
+
+#[allow(unconditional_recursion)]
+fn overflow(depth: u64) -> u64 {
+    overflow(black_box(depth + 1)) + 1
+}
+
+fn fail(message: core::fmt::Arguments) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("error: {}\n", message);
+    exit_qemu(QemuExitCode::Failed);
+    kernel::hlt_loop();
+}
+
+/// The page fault handler panics (after dumping the fault), still on its own stack.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp) };
+    let current = memory::stack::containing(VirtAddr::new(rsp)).map(|stack| stack.name);
+    let overflowed = Cr2::read()
+        .ok()
+        .and_then(memory::stack::overflowed_stack)
+        .map(|stack| stack.name);
+
+    if current == Some("page fault") && overflowed == Some("kernel") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+        kernel::hlt_loop();
+    }
+    fail(format_args!(
+        "panicked on {current:?} with the overflow in {overflowed:?}: {info}"
+    ));
+}
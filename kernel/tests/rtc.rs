@@ -0,0 +1,154 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use bootloader_api::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use kernel::time::{
+    self, Instant, SystemTime, TickSource, UNIX_EPOCH,
+    rtc::{self, DateTime, RtcError, RtcInterrupt},
+};
+use kernel::{acpi, memory};
+use x86_64::VirtAddr;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+    unsafe { acpi::init(boot_info.rsdp_addr.into_option()) }.expect("ACPI init failed");
+    kernel::interrupts::apic::init().expect("APIC init failed");
+    time::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn date_time(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+    DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+}
+
+#[test_case]
+fn unix_seconds_round_trip() {
+    let cases = [
+        (date_time(1970, 1, 1, 0, 0, 0), 0),
+        (date_time(2000, 3, 1, 0, 0, 0), 951_868_800),
+        (date_time(2024, 2, 29, 12, 0, 0), 1_709_208_000),
+        (date_time(2038, 1, 19, 3, 14, 8), 1 << 31),
+    ];
+    for (date_time, seconds) in cases {
+        assert_eq!(date_time.to_unix_seconds(), seconds);
+        assert_eq!(DateTime::from_unix_seconds(seconds), date_time);
+    }
+}
+
+#[test_case]
+fn formats_as_iso_8601() {
+    let leap_day = date_time(2024, 2, 29, 12, 0, 0);
+    assert_eq!(format!("{leap_day}"), "2024-02-29T12:00:00Z");
+    let time = SystemTime::from(leap_day) + Duration::from_millis(1234);
+    assert_eq!(format!("{time}"), "2024-02-29T12:00:01.234Z");
+}
+
+#[test_case]
+fn rtc_reads_a_sane_date() {
+    let now = rtc::read();
+    assert!(now.year >= 2024, "{now}");
+    assert!((1..=12).contains(&now.month));
+    assert!((1..=31).contains(&now.day));
+    assert!(now.hour < 24 && now.minute < 60 && now.second < 60);
+}
+
+#[test_case]
+fn system_time_follows_the_rtc() {
+    let rtc = SystemTime::from(rtc::read());
+    let now = SystemTime::now();
+    let drift = match now.duration_since(rtc) {
+        Ok(drift) => drift,
+        Err(err) => err.duration(),
+    };
+    // the RTC only counts whole seconds, and the two reads can straddle a tick
+    assert!(drift < Duration::from_secs(2), "{now} vs {rtc}");
+    assert!(now.duration_since(UNIX_EPOCH).unwrap() > Duration::from_secs(1_704_067_200));
+}
+
+#[test_case]
+fn system_time_advances() {
+    let start = SystemTime::now();
+    let instant = time::Instant::now();
+    while instant.elapsed() < Duration::from_millis(10) {
+        x86_64::instructions::hlt();
+    }
+    let elapsed = start.elapsed().unwrap();
+    assert!(elapsed >= Duration::from_millis(10));
+    assert!(start.duration_since(SystemTime::now()).is_err());
+}
+
+/// Halts until `count` goes past `start`, `false` if it didn't within `timeout`.
+fn wait_for(count: fn() -> u64, start: u64, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while count() == start {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        x86_64::instructions::hlt();
+    }
+    true
+}
+
+#[test_case]
+fn hpet_leaves_irq_8_alone() {
+    // QEMU has an HPET, which ticks through the I/O APIC instead of taking IRQs 0 and 8
+    assert_eq!(time::tick_source(), Some(TickSource::Hpet));
+}
+
+#[test_case]
+fn update_interrupt_fires() {
+    let start = rtc::update_interrupts();
+    rtc::enable_interrupt(RtcInterrupt::Update).unwrap();
+    // once a second, give it a bit more
+    let fired = wait_for(rtc::update_interrupts, start, Duration::from_millis(1500));
+    rtc::disable_interrupt(RtcInterrupt::Update);
+    assert!(fired, "no update interrupt in 1.5 s");
+}
+
+#[test_case]
+fn periodic_interrupt_fires() {
+    let start = rtc::periodic_interrupts();
+    // rate 6 is 1024 Hz
+    rtc::enable_interrupt(RtcInterrupt::Periodic { rate: 6 }).unwrap();
+    let fired = wait_for(rtc::periodic_interrupts, start, Duration::from_millis(100));
+    rtc::disable_interrupt(RtcInterrupt::Periodic { rate: 6 });
+    assert!(fired, "no periodic interrupt in 100 ms");
+}
+
+#[test_case]
+fn rejects_invalid_rates() {
+    for rate in [0, 2, 16] {
+        assert_eq!(
+            rtc::enable_interrupt(RtcInterrupt::Periodic { rate }),
+            Err(RtcError::InvalidRate(rate))
+        );
+    }
+}
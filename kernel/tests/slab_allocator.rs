@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{BootInfo, entry_point};
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+use kernel::allocator::{Locked, slab::SlabAllocator};
+
+const REGION_SIZE: usize = 64 * 1024;
+
+#[repr(align(4096))]
+struct Region {
+    _bytes: [u8; REGION_SIZE],
+}
+
+static mut REGION: Region = Region {
+    _bytes: [0; REGION_SIZE],
+};
+static SLAB: Locked<SlabAllocator> = Locked::new(SlabAllocator::new());
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(_boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    unsafe { SLAB.lock().init(&raw mut REGION as usize, REGION_SIZE) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn alloc_and_free() {
+    let layout = Layout::new::<u64>();
+    let ptr = unsafe { SLAB.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(ptr as usize % layout.align(), 0);
+
+    unsafe {
+        (ptr as *mut u64).write(0xdead_beef);
+        assert_eq!(*(ptr as *mut u64), 0xdead_beef);
+        SLAB.dealloc(ptr, layout);
+    }
+    assert_eq!(SLAB.lock().stats().live_allocations, 0);
+}
+
+#[test_case]
+fn freed_object_is_reused() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let first = SLAB.alloc(layout);
+        SLAB.dealloc(first, layout);
+        let second = SLAB.alloc(layout);
+        assert_eq!(first, second);
+        SLAB.dealloc(second, layout);
+    }
+}
+
+#[test_case]
+fn empty_slabs_go_back_to_the_heap() {
+    const COUNT: usize = 1024;
+    let small = Layout::new::<u64>();
+    let heap_free_before = SLAB.lock().stats().heap_free;
+
+    // spread 8 byte objects over a bunch of slabs, then free them all
+    let mut ptrs = [core::ptr::null_mut(); COUNT];
+    for ptr in ptrs.iter_mut() {
+        *ptr = unsafe { SLAB.alloc(small) };
+        assert!(!ptr.is_null());
+    }
+    for &ptr in ptrs.iter() {
+        unsafe { SLAB.dealloc(ptr, small) };
+    }
+
+    // only the one cached empty slab may still be holding memory
+    let heap_free_after = SLAB.lock().stats().heap_free;
+    assert!(heap_free_before - heap_free_after <= 4096);
+
+    // which means the memory can now be used by a different size class
+    let big = Layout::from_size_align(2048, 2048).unwrap();
+    let mut big_ptrs = [core::ptr::null_mut(); 8];
+    for ptr in big_ptrs.iter_mut() {
+        *ptr = unsafe { SLAB.alloc(big) };
+        assert!(!ptr.is_null());
+    }
+    for &ptr in big_ptrs.iter() {
+        unsafe { SLAB.dealloc(ptr, big) };
+    }
+}
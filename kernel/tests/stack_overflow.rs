@@ -1,18 +1,26 @@
+//! Overflows the boot stack with the kernel's own handlers, which have to pin the overflow on
+//! the boot stack's guard page before panicking.
+
 #![no_std]
 #![no_main]
-#![feature(abi_x86_interrupt)]
 
+use bootloader_api::{BootInfo, entry_point};
 use core::{hint::black_box, panic::PanicInfo};
-use lazy_static::lazy_static;
-use kernel::{QemuExitCode, exit_qemu, serial_print, serial_println};
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use kernel::interrupts::exceptions;
+use kernel::{QemuExitCode, exit_qemu, memory, serial_print, serial_println};
+use x86_64::VirtAddr;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
 
-#[unsafe(no_mangle)]
-pub extern "C" fn _start() -> ! {
+fn main(boot_info: &'static mut BootInfo) -> ! {
     serial_print!("stack_overflow::stack_overflow...\t");
 
-    kernel::gdt::init();
-    init_test_idt();
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+    kernel::gdt::init_stacks();
 
     black_box(stack_overflow());
 
@@ -24,32 +32,19 @@ fn stack_overflow() {
     stack_overflow(); // overflows because each call pushes its return address to the stack
 }
 
-lazy_static! {
-    static ref TEST_IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(test_double_fault_handler)
-                .set_stack_index(kernel::gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-        idt
-    };
-}
-
-pub fn init_test_idt() {
-    TEST_IDT.load();
-}
-
-extern "x86-interrupt" fn test_double_fault_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    serial_println!("[ok]");
-    exit_qemu(QemuExitCode::Success);
-    loop {}
-}
-
+/// The fault handler panics once it has logged the overflow.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    kernel::test_panic_handler(info)
+    match exceptions::overflowed_stack() {
+        Some("kernel") => {
+            serial_println!("[ok]");
+            exit_qemu(QemuExitCode::Success);
+        }
+        other => {
+            serial_println!("[failed]\n");
+            serial_println!("error: overflow not detected, got {:?}: {}\n", other, info);
+            exit_qemu(QemuExitCode::Failed);
+        }
+    }
+    kernel::hlt_loop();
 }
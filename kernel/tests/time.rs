@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use kernel::time::{self, Instant, TickSource, tsc};
+use kernel::{acpi, memory};
+use x86_64::VirtAddr;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+    unsafe { acpi::init(boot_info.rsdp_addr.into_option()) }.expect("ACPI init failed");
+    kernel::interrupts::apic::init().expect("APIC init failed");
+    time::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn tsc_is_calibrated() {
+    let hz = tsc::frequency().unwrap();
+    assert!(hz > 100_000_000, "TSC at {hz} Hz");
+    // QEMU always has an HPET, with I/O APIC inputs past the ISA IRQs for timer 0
+    assert_eq!(time::tick_source(), Some(TickSource::Hpet));
+}
+
+#[test_case]
+fn clock_is_monotonic_across_interrupts() {
+    let mut last = Instant::now();
+    for _ in 0..50 {
+        x86_64::instructions::hlt();
+        for _ in 0..100 {
+            let now = Instant::now();
+            assert!(now >= last);
+            last = now;
+        }
+    }
+}
+
+#[test_case]
+fn ticks_follow_the_clock() {
+    let start = Instant::now();
+    let ticks = time::ticks();
+    while start.elapsed() < Duration::from_millis(50) {
+        x86_64::instructions::hlt();
+    }
+    let elapsed = time::ticks() - ticks;
+    // 50 at TICK_HZ = 1000, leave room for an emulator that's running behind
+    assert!((25..=100).contains(&elapsed), "{elapsed} ticks in 50 ms");
+}
+
+#[test_case]
+fn instant_arithmetic() {
+    let now = Instant::now();
+    let later = now + Duration::from_millis(1500);
+    assert_eq!(later - now, Duration::from_millis(1500));
+    assert_eq!(now - later, Duration::ZERO);
+    assert_eq!(later - Duration::from_millis(1500), now);
+    assert_eq!(Instant::ZERO.checked_sub(Duration::from_nanos(1)), None);
+    assert!(time::uptime() >= now.duration_since(Instant::ZERO));
+}
@@ -0,0 +1,155 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, task::Wake};
+use bootloader_api::{BootInfo, entry_point};
+use core::future::{Future, pending};
+use core::panic::PanicInfo;
+use core::pin::{Pin, pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use kernel::task::timer::{self, Elapsed};
+use kernel::time::{self, Instant};
+use kernel::{acpi, memory};
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset =
+        VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("msg"));
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    kernel::allocator::init_heap().expect("heap init failed :(");
+    unsafe { acpi::init(boot_info.rsdp_addr.into_option()) }.expect("ACPI init failed");
+    let _ = kernel::interrupts::apic::init();
+    time::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+struct FlagWaker(AtomicBool);
+
+impl Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Polls `future` only when it was woken, halting in between, so anything that finishes had to
+/// be woken by the timer interrupt.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let flag = Arc::new(FlagWaker(AtomicBool::new(true)));
+    let waker = Waker::from(flag.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if flag.0.swap(false, Ordering::SeqCst)
+            && let Poll::Ready(output) = future.as_mut().poll(&mut context)
+        {
+            return output;
+        }
+        interrupts::disable();
+        if flag.0.load(Ordering::SeqCst) {
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+#[test_case]
+fn sleep_waits_and_wakes() {
+    let start = Instant::now();
+    block_on(timer::sleep(Duration::from_millis(20)));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert_eq!(timer::pending(), 0);
+}
+
+#[test_case]
+fn sleep_until_the_past_is_ready() {
+    block_on(timer::sleep_until(Instant::ZERO));
+}
+
+#[test_case]
+fn sleeps_wake_in_deadline_order() {
+    let start = Instant::now();
+    let result = block_on(async {
+        let long = timer::timeout(Duration::from_millis(30), pending::<()>());
+        let short = timer::sleep(Duration::from_millis(10));
+        short.await;
+        let short_done = start.elapsed();
+        (long.await, short_done)
+    });
+    assert_eq!(result.0, Err(Elapsed));
+    assert!(result.1 >= Duration::from_millis(10));
+    assert!(start.elapsed() >= Duration::from_millis(30));
+}
+
+#[test_case]
+fn timeout_lets_fast_futures_finish() {
+    let result = block_on(timer::timeout(Duration::from_secs(1), async {
+        timer::sleep(Duration::from_millis(5)).await;
+        42
+    }));
+    assert_eq!(result, Ok(42));
+    // the timeout's own timer got cancelled
+    assert_eq!(timer::pending(), 0);
+}
+
+#[test_case]
+fn interval_ticks_every_period() {
+    let period = Duration::from_millis(10);
+    let mut interval = timer::interval(period);
+    let ticks = block_on(async {
+        let mut ticks = [Instant::ZERO; 4];
+        for tick in &mut ticks {
+            *tick = interval.tick().await;
+        }
+        ticks
+    });
+    for pair in ticks.windows(2) {
+        assert!(pair[1] - pair[0] >= period);
+    }
+}
+
+#[test_case]
+fn dropped_sleeps_are_cancelled() {
+    let mut sleep = timer::sleep(Duration::from_secs(10));
+    let waker = Waker::from(Arc::new(FlagWaker(AtomicBool::new(false))));
+    let mut context = Context::from_waker(&waker);
+    assert!(Pin::new(&mut sleep).poll(&mut context).is_pending());
+    assert_eq!(timer::pending(), 1);
+    drop(sleep);
+    assert_eq!(timer::pending(), 0);
+}
+
+#[test_case]
+fn huge_durations_never_fire() {
+    // `Duration::MAX` is the usual way to say "no timeout"
+    let result = block_on(timer::timeout(Duration::MAX, async { 7 }));
+    assert_eq!(result, Ok(7));
+
+    let mut sleep = pin!(timer::sleep(Duration::MAX));
+    let waker = Waker::noop();
+    assert!(
+        sleep
+            .as_mut()
+            .poll(&mut Context::from_waker(waker))
+            .is_pending()
+    );
+    assert_eq!(sleep.deadline(), Instant::MAX);
+}